@@ -0,0 +1,77 @@
+use fs4::tokio::AsyncFileExt;
+use tokio::{
+    fs::File,
+    io::{self, AsyncSeekExt, AsyncWriteExt, SeekFrom},
+};
+
+/// How disk space is reserved before the download starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileAllocation {
+    /// Let the file grow as data arrives
+    None,
+    /// Set the length up front, leaving a sparse file where supported
+    #[default]
+    Truncate,
+    /// Reserve real blocks with `fallocate`, failing early on a full disk
+    Falloc,
+    /// Write zeros up to the full size; slow, but avoids fragmentation
+    Full,
+}
+
+impl FileAllocation {
+    /// Parses aria2's `--file-allocation` values.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "none" => Some(Self::None),
+            "trunc" | "truncate" | "sparse" => Some(Self::Truncate),
+            "falloc" => Some(Self::Falloc),
+            "prealloc" | "full" => Some(Self::Full),
+            _ => None,
+        }
+    }
+}
+
+/// Grows `file` to `size`; bytes that are already there are left untouched.
+pub async fn allocate(file: &mut File, size: u64, mode: FileAllocation) -> io::Result<()> {
+    let len = file.metadata().await?.len();
+    if len >= size {
+        return Ok(());
+    }
+    match mode {
+        FileAllocation::None => {}
+        FileAllocation::Truncate => file.set_len(size).await?,
+        FileAllocation::Falloc => file.allocate(size).await?,
+        FileAllocation::Full => {
+            let zeros = vec![0; 1024 * 1024];
+            file.seek(SeekFrom::Start(len)).await?;
+            let mut remaining = size - len;
+            while remaining > 0 {
+                let n = remaining.min(zeros.len() as u64) as usize;
+                file.write_all(&zeros[..n]).await?;
+                remaining -= n as u64;
+            }
+            file.flush().await?;
+            file.seek(SeekFrom::Start(0)).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_file_allocation() {
+        assert_eq!(FileAllocation::parse("none"), Some(FileAllocation::None));
+        assert_eq!(
+            FileAllocation::parse("trunc"),
+            Some(FileAllocation::Truncate)
+        );
+        assert_eq!(
+            FileAllocation::parse("prealloc"),
+            Some(FileAllocation::Full)
+        );
+        assert_eq!(FileAllocation::parse("bogus"), None);
+    }
+}
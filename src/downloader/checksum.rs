@@ -0,0 +1,113 @@
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+use std::path::Path;
+use tokio::{
+    fs::File,
+    io::{self, AsyncReadExt},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgo {
+    Sha224,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashAlgo {
+    /// Digest size in bytes.
+    pub fn digest_len(self) -> usize {
+        match self {
+            Self::Sha224 => 28,
+            Self::Sha256 => 32,
+            Self::Sha384 => 48,
+            Self::Sha512 => 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChecksumError {
+    Invalid,
+    /// An algorithm aria2 accepts but that is not implemented here, e.g. `md5`
+    UnsupportedAlgo(String),
+}
+
+/// An expected digest in aria2's `TYPE=DIGEST` form, e.g. `sha-256=<hex>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksum {
+    pub algo: HashAlgo,
+    pub digest: Vec<u8>,
+}
+
+impl Checksum {
+    pub fn parse(value: &str) -> Result<Self, ChecksumError> {
+        let (algo, hex) = value.split_once('=').ok_or(ChecksumError::Invalid)?;
+        let algo = match algo.to_ascii_lowercase().as_str() {
+            "sha-224" => HashAlgo::Sha224,
+            "sha-256" => HashAlgo::Sha256,
+            "sha-384" => HashAlgo::Sha384,
+            "sha-512" => HashAlgo::Sha512,
+            "md5" | "sha-1" => return Err(ChecksumError::UnsupportedAlgo(algo.into())),
+            _ => return Err(ChecksumError::Invalid),
+        };
+        if hex.len() != algo.digest_len() * 2 {
+            return Err(ChecksumError::Invalid);
+        }
+        let digest = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<_>>>()
+            .ok_or(ChecksumError::Invalid)?;
+        Ok(Self { algo, digest })
+    }
+
+    pub async fn verify(&self, path: impl AsRef<Path>) -> io::Result<bool> {
+        let digest = match self.algo {
+            HashAlgo::Sha224 => hash_file::<Sha224>(path.as_ref()).await?,
+            HashAlgo::Sha256 => hash_file::<Sha256>(path.as_ref()).await?,
+            HashAlgo::Sha384 => hash_file::<Sha384>(path.as_ref()).await?,
+            HashAlgo::Sha512 => hash_file::<Sha512>(path.as_ref()).await?,
+        };
+        Ok(digest == self.digest)
+    }
+}
+
+async fn hash_file<D: Digest>(path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path).await?;
+    let mut hasher = D::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let hex = "ab".repeat(32);
+        let checksum = Checksum::parse(&format!("SHA-256={hex}")).unwrap();
+        assert_eq!(checksum.algo, HashAlgo::Sha256);
+        assert_eq!(checksum.digest, vec![0xab; 32]);
+        assert_eq!(Checksum::parse("sha-256="), Err(ChecksumError::Invalid));
+        assert_eq!(
+            Checksum::parse(&format!("sha-512={hex}")),
+            Err(ChecksumError::Invalid)
+        );
+        assert_eq!(
+            Checksum::parse(&format!("sha-256={}", "zz".repeat(32))),
+            Err(ChecksumError::Invalid)
+        );
+        assert_eq!(
+            Checksum::parse("md5=d41d8cd98f00b204e9800998ecf8427e"),
+            Err(ChecksumError::UnsupportedAlgo("md5".into()))
+        );
+    }
+}
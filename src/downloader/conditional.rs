@@ -0,0 +1,214 @@
+use crate::puller::ClientOptions;
+use fast_down::UrlInfo;
+use reqwest::header::{HeaderMap, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+use tokio::{fs, io};
+
+const MAGIC: &str = "fast-down-validators 1";
+
+/// Sidecar keeping the server's `ETag` and `Last-Modified` of a finished
+/// download, so that updates after a restart or from a new task can still
+/// send a conditional request.
+pub fn validators_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".validators");
+    name.into()
+}
+
+/// The `ETag` and `Last-Modified` recorded in the sidecar.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recorded {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Recorded {
+    pub fn new(info: &UrlInfo) -> Self {
+        Self {
+            etag: info.file_id.etag.as_deref().map(Into::into),
+            last_modified: info.file_id.last_modified.as_deref().map(Into::into),
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let mut content = format!("{MAGIC}\n");
+        if let Some(etag) = &self.etag {
+            content.push_str(&format!("etag\t{etag}\n"));
+        }
+        if let Some(last_modified) = &self.last_modified {
+            content.push_str(&format!("last-modified\t{last_modified}\n"));
+        }
+        content
+    }
+
+    pub fn decode(content: &str) -> Option<Self> {
+        let mut lines = content.lines();
+        if lines.next()? != MAGIC {
+            return None;
+        }
+        let mut recorded = Self::default();
+        for line in lines {
+            match line.split_once('\t')? {
+                ("etag", value) => recorded.etag = Some(value.to_string()),
+                ("last-modified", value) => recorded.last_modified = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        Some(recorded)
+    }
+
+    /// Returns `Ok(None)` when there is no usable sidecar.
+    pub async fn load(path: &Path) -> io::Result<Option<Self>> {
+        match fs::read_to_string(validators_path(path)).await {
+            Ok(content) => Ok(Self::decode(&content)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Removes a stale sidecar when the server sent neither header.
+    pub async fn save(&self, path: &Path) -> io::Result<()> {
+        let sidecar = validators_path(path);
+        if self.etag.is_none() && self.last_modified.is_none() {
+            return match fs::remove_file(&sidecar).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        let mut tmp = sidecar.as_os_str().to_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, self.encode()).await?;
+        fs::rename(&tmp, &sidecar).await
+    }
+}
+
+/// What is known about the local copy of a file, used to ask the server
+/// whether it changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<SystemTime>,
+    pub len: u64,
+}
+
+impl Validators {
+    /// Returns `None` when there is no local file. Without a `previous` run
+    /// in this process the sidecar is read instead, and the modification time
+    /// of the file stands in when the server's `Last-Modified` was not
+    /// recorded.
+    pub async fn load(path: &Path, previous: Option<&UrlInfo>) -> io::Result<Option<Self>> {
+        let metadata = match fs::metadata(path).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let recorded = match previous {
+            Some(info) => Recorded::new(info),
+            None => Recorded::load(path).await?.unwrap_or_default(),
+        };
+        let date = recorded.last_modified.as_deref();
+        Ok(Some(Self {
+            etag: recorded.etag,
+            last_modified: match date.and_then(|date| httpdate::parse_http_date(date).ok()) {
+                Some(date) => Some(date),
+                None => metadata.modified().ok(),
+            },
+            len: metadata.len(),
+        }))
+    }
+
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = &self.etag
+            && let Ok(value) = HeaderValue::from_str(etag)
+        {
+            headers.insert(IF_NONE_MATCH, value);
+        }
+        if let Some(date) = self.last_modified
+            && let Ok(value) = HeaderValue::from_str(&httpdate::fmt_http_date(date))
+        {
+            headers.insert(IF_MODIFIED_SINCE, value);
+        }
+        headers
+    }
+
+    /// `options` with the conditional headers added, so that the prefetch
+    /// itself asks whether the file changed and a `200` response can be kept
+    /// for the download. `None` when there is nothing to compare against.
+    pub fn conditional(&self, options: &ClientOptions) -> Option<ClientOptions> {
+        let conditions = self.headers();
+        if conditions.is_empty() {
+            return None;
+        }
+        let mut headers = options.headers.as_ref().clone();
+        headers.extend(conditions);
+        Some(ClientOptions {
+            headers: Arc::new(headers),
+            ..options.clone()
+        })
+    }
+
+    /// The same check done locally against a fresh prefetch.
+    pub fn unchanged(&self, info: &UrlInfo) -> bool {
+        if self.len != info.size {
+            return false;
+        }
+        if let Some(etag) = info.file_id.etag.as_deref() {
+            return self.etag.as_deref() == Some(etag);
+        }
+        let remote = info
+            .file_id
+            .last_modified
+            .as_deref()
+            .and_then(|date| httpdate::parse_http_date(date).ok());
+        matches!((self.last_modified, remote), (Some(local), Some(remote)) if local >= remote)
+    }
+}
+
+/// Stamps a finished download with the server's `Last-Modified`, so that the
+/// next update compares against the remote time rather than the download time.
+pub async fn set_modified(path: &Path, info: &UrlInfo) -> io::Result<()> {
+    let Some(date) = info
+        .file_id
+        .last_modified
+        .as_deref()
+        .and_then(|date| httpdate::parse_http_date(date).ok())
+    else {
+        return Ok(());
+    };
+    let file = fs::OpenOptions::new().write(true).open(path).await?;
+    file.into_std().await.set_modified(date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_validator_headers() {
+        let validators = Validators {
+            etag: Some("\"v1\"".into()),
+            last_modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(784111777)),
+            len: 0,
+        };
+        let headers = validators.headers();
+        assert_eq!(headers[IF_NONE_MATCH], "\"v1\"");
+        assert_eq!(headers[IF_MODIFIED_SINCE], "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert!(Validators::default().headers().is_empty());
+    }
+
+    #[test]
+    fn test_recorded_roundtrip() {
+        let recorded = Recorded {
+            etag: Some("\"v1\"".into()),
+            last_modified: Some("Sun, 06 Nov 1994 08:49:37 GMT".into()),
+        };
+        assert_eq!(Recorded::decode(&recorded.encode()), Some(recorded));
+        assert_eq!(Recorded::decode("etag\t\"v1\"\n"), None);
+    }
+}
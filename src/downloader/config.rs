@@ -1,3 +1,11 @@
+use crate::{
+    allocation::FileAllocation,
+    auth::{Secret, redact_headers, redact_url},
+    cookie_jar::CookieJar,
+    dns::{DnsResolver, IpPreference},
+    file_conflict::{FileChanged, FileConflict},
+    hooks::Hook,
+};
 use inherit_config_derive::Config;
 use reqwest::header::HeaderMap;
 use std::{
@@ -6,15 +14,37 @@ use std::{
     sync::Arc,
     time::Duration,
 };
+use url::Url;
 
 #[derive(Debug, Clone, Config)]
 pub struct DownloadConfig {
     #[config(default = Some(NonZero::new(32).unwrap()))]
     pub threads: Option<NonZeroUsize>,
 
+    #[config(default = Some(Arc::from("")))]
+    pub http_proxy: Option<Arc<str>>,
+
+    #[config(default = Some(Arc::from("")))]
+    pub https_proxy: Option<Arc<str>>,
+
+    #[config(default = Some(Arc::from("")))]
+    pub all_proxy: Option<Arc<str>>,
+
+    /// Deprecated: the single proxy of earlier versions, now an alias of
+    /// `all_proxy` that applies when `all_proxy` is empty.
     #[config(default = Some(Arc::from("")))]
     pub proxy: Option<Arc<str>>,
 
+    /// Comma-separated hosts, domains and CIDR ranges that bypass the proxies
+    #[config(default = Some(Arc::from("")))]
+    pub no_proxy: Option<Arc<str>>,
+
+    #[config(default = Some(Arc::from("")))]
+    pub proxy_user: Option<Arc<str>>,
+
+    #[config(default = Some(Secret::default()))]
+    pub proxy_passwd: Option<Secret>,
+
     #[config(default = Some(Arc::new(HeaderMap::new())))]
     pub headers: Option<Arc<HeaderMap>>,
 
@@ -41,4 +71,291 @@ pub struct DownloadConfig {
 
     #[config(default = Some(NonZero::new(1024 * 1024).unwrap()))]
     pub min_chunk_size: Option<NonZeroU64>,
+
+    #[config(default = Some(Arc::from("")))]
+    pub http_user: Option<Arc<str>>,
+
+    #[config(default = Some(Secret::default()))]
+    pub http_passwd: Option<Secret>,
+
+    #[config(default = Some(Secret::default()))]
+    pub bearer_token: Option<Secret>,
+
+    /// Used when the `ftp://` URL carries no credentials; empty means anonymous
+    #[config(default = Some(Arc::from("")))]
+    pub ftp_user: Option<Arc<str>>,
+
+    #[config(default = Some(Secret::default()))]
+    pub ftp_passwd: Option<Secret>,
+
+    /// Passive mode (`EPSV`/`PASV`); active mode (`PORT`) otherwise
+    #[config(default = Some(true))]
+    pub ftp_passive: Option<bool>,
+
+    #[config(default = Some(false))]
+    pub netrc: Option<bool>,
+
+    /// Empty means `~/.netrc` (`%USERPROFILE%\_netrc` on Windows)
+    #[config(default = Some(Path::new("").into()))]
+    pub netrc_path: Option<Arc<Path>>,
+
+    #[config(default = Some(CookieJar::default()))]
+    pub cookie_jar: Option<CookieJar>,
+
+    /// Netscape `cookies.txt` imported into `cookie_jar` when a task starts
+    #[config(default = Some(Path::new("").into()))]
+    pub load_cookies: Option<Arc<Path>>,
+
+    /// Where `cookie_jar` is written back after a task finishes
+    #[config(default = Some(Path::new("").into()))]
+    pub save_cookies: Option<Arc<Path>>,
+
+    /// curl `--resolve` style host overrides: `host:port:addr[,addr]...`
+    #[config(default = Some(Arc::from([])))]
+    pub resolve: Option<Arc<[Arc<str>]>>,
+
+    #[config(default = Some(IpPreference::Auto))]
+    pub ip_preference: Option<IpPreference>,
+
+    #[config(default = Some(DnsResolver::system()))]
+    pub dns_resolver: Option<DnsResolver>,
+
+    /// Extra PEM bundles trusted in addition to the built-in roots
+    #[config(default = Some(Arc::from([])))]
+    pub ca_certs: Option<Arc<[Arc<Path>]>>,
+
+    /// PEM certificate chain, or a `.p12`/`.pfx` bundle
+    #[config(default = Some(Path::new("").into()))]
+    pub client_cert: Option<Arc<Path>>,
+
+    /// PEM private key; empty means it is stored in `client_cert`
+    #[config(default = Some(Path::new("").into()))]
+    pub client_key: Option<Arc<Path>>,
+
+    #[config(default = Some(Secret::default()))]
+    pub client_cert_passwd: Option<Secret>,
+
+    /// SHA-256 fingerprints of accepted server certificates
+    #[config(default = Some(Arc::from([])))]
+    pub pinned_certs: Option<Arc<[Arc<str>]>>,
+
+    #[config(default = Some(FileConflict::Rename))]
+    pub file_conflict: Option<FileConflict>,
+
+    /// What to do when the remote file changed since the task started
+    #[config(default = Some(FileChanged::Fail))]
+    pub file_changed: Option<FileChanged>,
+
+    /// Write to `<name>.part` and rename once the download is complete
+    #[config(default = Some(true))]
+    pub part_file: Option<bool>,
+
+    /// Directory for `.part` files; empty means next to the target file
+    #[config(default = Some(Path::new("").into()))]
+    pub part_dir: Option<Arc<Path>>,
+
+    /// Keep a `.control` sidecar next to the written file so that progress
+    /// survives a crash
+    #[config(default = Some(true))]
+    pub control_file: Option<bool>,
+
+    /// How often the control file is flushed while downloading
+    #[config(default = Some(Duration::from_secs(1)))]
+    pub control_interval: Option<Duration>,
+
+    /// How space for the whole file is reserved before downloading
+    #[config(default = Some(FileAllocation::Truncate))]
+    pub file_allocation: Option<FileAllocation>,
+
+    /// Bytes to keep free in the download directory; tasks pause below this
+    #[config(default = Some(0))]
+    pub min_free_space: Option<u64>,
+
+    /// How often free space is checked while downloading or waiting for room
+    #[config(default = Some(Duration::from_secs(5)))]
+    pub disk_check_interval: Option<Duration>,
+
+    #[config(default = Some(Arc::from([])))]
+    pub on_start: Option<Arc<[Hook]>>,
+
+    #[config(default = Some(Arc::from([])))]
+    pub on_complete: Option<Arc<[Hook]>>,
+
+    #[config(default = Some(Arc::from([])))]
+    pub on_error: Option<Arc<[Hook]>>,
+
+    #[config(default = Some(Arc::from([])))]
+    pub on_pause: Option<Arc<[Hook]>>,
+
+    /// Commands are killed and webhooks abandoned after this long
+    #[config(default = Some(Duration::from_secs(30)))]
+    pub hook_timeout: Option<Duration>,
+
+    /// Unpack `.zip`, `.tar`, `.tar.gz` and `.tar.zst` files once downloaded
+    #[config(default = Some(false))]
+    pub extract: Option<bool>,
+
+    /// Empty means a directory named after the archive, next to it
+    #[config(default = Some(Path::new("").into()))]
+    pub extract_dir: Option<Arc<Path>>,
+
+    /// Abort once the unpacked size exceeds this many bytes; `0` is unlimited
+    #[config(default = Some(0))]
+    pub extract_max_size: Option<u64>,
+
+    #[config(default = Some(false))]
+    pub extract_remove_archive: Option<bool>,
+}
+
+macro_rules! set_fields {
+    ($config:expr, $($field:ident),* $(,)?) => {
+        [$((stringify!($field), $config.$field.is_some())),*]
+            .into_iter()
+            .filter_map(|(name, set)| set.then_some(name))
+            .collect()
+    };
+}
+
+impl DownloadConfig {
+    /// Names of the fields that are set, i.e. not inherited.
+    pub fn set_fields(&self) -> Vec<&'static str> {
+        set_fields!(
+            self,
+            threads,
+            http_proxy,
+            https_proxy,
+            all_proxy,
+            proxy,
+            no_proxy,
+            proxy_user,
+            proxy_passwd,
+            headers,
+            accept_invalid_certs,
+            accept_invalid_hostnames,
+            multiplexing,
+            save_dir,
+            write_queue_cap,
+            write_buffer_size,
+            retry_gap,
+            min_chunk_size,
+            http_user,
+            http_passwd,
+            bearer_token,
+            ftp_user,
+            ftp_passwd,
+            ftp_passive,
+            netrc,
+            netrc_path,
+            cookie_jar,
+            load_cookies,
+            save_cookies,
+            resolve,
+            ip_preference,
+            dns_resolver,
+            ca_certs,
+            client_cert,
+            client_key,
+            client_cert_passwd,
+            pinned_certs,
+            file_conflict,
+            file_changed,
+            part_file,
+            part_dir,
+            control_file,
+            control_interval,
+            file_allocation,
+            min_free_space,
+            disk_check_interval,
+            on_start,
+            on_complete,
+            on_error,
+            on_pause,
+            hook_timeout,
+            extract,
+            extract_dir,
+            extract_max_size,
+            extract_remove_archive,
+        )
+    }
+
+    /// A copy for logs, with credentials in headers and proxy URLs masked.
+    pub fn redacted(&self) -> Self {
+        let proxy = |proxy: &Option<Arc<str>>| {
+            proxy.as_deref().map(|proxy| match Url::parse(proxy) {
+                Ok(url) => redact_url(&url).as_str().into(),
+                Err(_) => proxy.into(),
+            })
+        };
+        Self {
+            http_proxy: proxy(&self.http_proxy),
+            https_proxy: proxy(&self.https_proxy),
+            all_proxy: proxy(&self.all_proxy),
+            proxy: proxy(&self.proxy),
+            headers: self
+                .headers
+                .as_deref()
+                .map(|headers| Arc::new(redact_headers(headers))),
+            ..self.clone()
+        }
+    }
+
+    /// A config with every field unset, so that all of them are inherited.
+    pub fn empty() -> Self {
+        Self {
+            threads: None,
+            http_proxy: None,
+            https_proxy: None,
+            all_proxy: None,
+            proxy: None,
+            no_proxy: None,
+            proxy_user: None,
+            proxy_passwd: None,
+            headers: None,
+            accept_invalid_certs: None,
+            accept_invalid_hostnames: None,
+            multiplexing: None,
+            save_dir: None,
+            write_queue_cap: None,
+            write_buffer_size: None,
+            retry_gap: None,
+            min_chunk_size: None,
+            http_user: None,
+            http_passwd: None,
+            bearer_token: None,
+            ftp_user: None,
+            ftp_passwd: None,
+            ftp_passive: None,
+            netrc: None,
+            netrc_path: None,
+            cookie_jar: None,
+            load_cookies: None,
+            save_cookies: None,
+            resolve: None,
+            ip_preference: None,
+            dns_resolver: None,
+            ca_certs: None,
+            client_cert: None,
+            client_key: None,
+            client_cert_passwd: None,
+            pinned_certs: None,
+            file_conflict: None,
+            file_changed: None,
+            part_file: None,
+            part_dir: None,
+            control_file: None,
+            control_interval: None,
+            file_allocation: None,
+            min_free_space: None,
+            disk_check_interval: None,
+            on_start: None,
+            on_complete: None,
+            on_error: None,
+            on_pause: None,
+            hook_timeout: None,
+            extract: None,
+            extract_dir: None,
+            extract_max_size: None,
+            extract_remove_archive: None,
+        }
+    }
 }
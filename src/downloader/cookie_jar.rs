@@ -0,0 +1,158 @@
+use cookie::{Cookie, time::OffsetDateTime};
+use cookie_store::{CookieDomain, CookieExpiration};
+use reqwest_cookie_store::CookieStoreMutex;
+use std::{
+    fmt::{self, Debug},
+    path::Path,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{fs, io};
+use url::Url;
+
+#[derive(Clone, Default)]
+pub struct CookieJar(Arc<CookieStoreMutex>);
+
+impl Debug for CookieJar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = self.0.lock().unwrap().iter_unexpired().count();
+        f.debug_struct("CookieJar")
+            .field("cookies", &count)
+            .finish()
+    }
+}
+
+impl PartialEq for CookieJar {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl CookieJar {
+    pub fn store(&self) -> Arc<CookieStoreMutex> {
+        self.0.clone()
+    }
+
+    /// Imports cookies in the Netscape `cookies.txt` format, returning how many were accepted.
+    pub fn import_netscape(&self, content: &str) -> usize {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        let mut store = self.0.lock().unwrap();
+        let mut count = 0;
+        for line in content.lines() {
+            let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+                Some(line) => (line, true),
+                None if line.starts_with('#') => continue,
+                None => (line, false),
+            };
+            let fields: Vec<_> = line.split('\t').collect();
+            let [
+                domain,
+                include_subdomains,
+                path,
+                secure,
+                expires,
+                name,
+                value,
+            ] = fields[..]
+            else {
+                continue;
+            };
+            let host = domain.trim_start_matches('.');
+            let Ok(url) = Url::parse(&format!("https://{host}{path}")) else {
+                continue;
+            };
+            let mut cookie = Cookie::build((name, value))
+                .path(path)
+                .secure(secure.eq_ignore_ascii_case("TRUE"))
+                .http_only(http_only);
+            if include_subdomains.eq_ignore_ascii_case("TRUE") {
+                cookie = cookie.domain(host);
+            }
+            match expires.parse::<i64>() {
+                Ok(0) | Err(_) => {}
+                Ok(expires) if expires <= now => continue,
+                Ok(expires) => {
+                    if let Ok(expires) = OffsetDateTime::from_unix_timestamp(expires) {
+                        cookie = cookie.expires(expires);
+                    }
+                }
+            }
+            if store.insert_raw(&cookie.build(), &url).is_ok() {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    pub fn export_netscape(&self) -> String {
+        let store = self.0.lock().unwrap();
+        let mut content = String::from("# Netscape HTTP Cookie File\n");
+        for cookie in store.iter_unexpired() {
+            let (domain, include_subdomains) = match &cookie.domain {
+                CookieDomain::HostOnly(domain) => (domain.clone(), "FALSE"),
+                CookieDomain::Suffix(domain) => (format!(".{domain}"), "TRUE"),
+                CookieDomain::NotPresent | CookieDomain::Empty => continue,
+            };
+            let expires = match &cookie.expires {
+                CookieExpiration::AtUtc(time) => time.unix_timestamp(),
+                CookieExpiration::SessionEnd => 0,
+            };
+            if cookie.http_only().unwrap_or(false) {
+                content.push_str("#HttpOnly_");
+            }
+            content.push_str(&format!(
+                "{domain}\t{include_subdomains}\t{}\t{}\t{expires}\t{}\t{}\n",
+                String::from(&cookie.path),
+                if cookie.secure().unwrap_or(false) {
+                    "TRUE"
+                } else {
+                    "FALSE"
+                },
+                cookie.name(),
+                cookie.value(),
+            ));
+        }
+        content
+    }
+
+    pub async fn load(&self, path: impl AsRef<Path>) -> io::Result<usize> {
+        let content = fs::read_to_string(path).await?;
+        Ok(self.import_netscape(&content))
+    }
+
+    pub async fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, self.export_netscape()).await?;
+        fs::rename(&tmp, path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_netscape_round_trip() {
+        let jar = CookieJar::default();
+        let imported = jar.import_netscape(
+            "# Netscape HTTP Cookie File\n\
+             .example.com\tTRUE\t/\tFALSE\t0\tsession\tabc\n\
+             #HttpOnly_login.example.com\tFALSE\t/app\tTRUE\t4102444800\ttoken\txyz\n\
+             expired.example.com\tFALSE\t/\tFALSE\t1\told\tgone\n",
+        );
+        assert_eq!(imported, 2);
+        let exported = jar.export_netscape();
+        assert!(exported.contains(".example.com\tTRUE\t/\tFALSE\t0\tsession\tabc\n"));
+        assert!(
+            exported.contains(
+                "#HttpOnly_login.example.com\tFALSE\t/app\tTRUE\t4102444800\ttoken\txyz\n"
+            )
+        );
+        assert!(!exported.contains("gone"));
+    }
+}
@@ -0,0 +1,139 @@
+use spin::mutex::SpinMutex;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// The filesystem holding a directory, so that reservations in different
+/// directories of one filesystem count against each other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Volume {
+    #[cfg_attr(not(unix), allow(dead_code))]
+    Device(u64),
+    /// Where the device cannot be told: the path itself, or its drive
+    Path(PathBuf),
+}
+
+impl Volume {
+    #[cfg(unix)]
+    fn of(dir: &Path) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        match std::fs::metadata(dir) {
+            Ok(metadata) => Self::Device(metadata.dev()),
+            Err(_) => Self::Path(dir.into()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn of(dir: &Path) -> Self {
+        match std::fs::canonicalize(dir) {
+            Ok(dir) => Self::Path(
+                dir.components()
+                    .next()
+                    .map_or_else(PathBuf::new, |c| PathBuf::from(c.as_os_str())),
+            ),
+            Err(_) => Self::Path(dir.into()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Reservations {
+    next_id: u64,
+    list: Vec<(u64, Volume, u64)>,
+}
+
+/// Bytes that running tasks still expect to write, so that tasks starting on
+/// the same filesystem do not count the same free space twice.
+#[derive(Debug, Clone, Default)]
+pub struct DiskSpace(Arc<SpinMutex<Reservations>>);
+
+impl DiskSpace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reserve(&self, dir: PathBuf, bytes: u64) -> Reservation {
+        let mut guard = self.0.lock();
+        let id = guard.next_id;
+        guard.next_id += 1;
+        guard.list.push((id, Volume::of(&dir), bytes));
+        Reservation {
+            space: self.clone(),
+            id,
+        }
+    }
+
+    fn reserved(&self, dir: &Path, except: Option<u64>) -> u64 {
+        let volume = Volume::of(dir);
+        self.0
+            .lock()
+            .list
+            .iter()
+            .filter(|(id, other, _)| Some(*id) != except && *other == volume)
+            .map(|(_, _, bytes)| bytes)
+            .sum()
+    }
+
+    /// Free space in `dir` minus what other tasks have reserved there.
+    pub fn available(&self, dir: &Path, own: Option<&Reservation>) -> io::Result<u64> {
+        let free = fs4::available_space(dir)?;
+        Ok(free.saturating_sub(self.reserved(dir, own.map(|r| r.id))))
+    }
+}
+
+/// Released when dropped, including when the task is aborted.
+#[derive(Debug)]
+pub struct Reservation {
+    space: DiskSpace,
+    id: u64,
+}
+
+impl Reservation {
+    pub fn update(&self, bytes: u64) {
+        let mut guard = self.space.0.lock();
+        if let Some(entry) = guard.list.iter_mut().find(|(id, _, _)| *id == self.id) {
+            entry.2 = bytes;
+        }
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.space.0.lock().list.retain(|(id, _, _)| *id != self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reservations() {
+        let space = DiskSpace::new();
+        let dir = Path::new("/data");
+        let a = space.reserve(dir.into(), 100);
+        let b = space.reserve(dir.into(), 50);
+        let _other = space.reserve("/other".into(), 1000);
+        assert_eq!(space.reserved(dir, Some(a.id)), 50);
+        b.update(20);
+        assert_eq!(space.reserved(dir, None), 120);
+        drop(a);
+        assert_eq!(space.reserved(dir, None), 20);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reservations_share_filesystem() {
+        let space = DiskSpace::new();
+        let root = std::env::temp_dir();
+        let sub = root.join(format!("disk-space-{}", std::process::id()));
+        std::fs::create_dir_all(&sub).unwrap();
+        let _a = space.reserve(root.clone(), 100);
+        let _b = space.reserve(sub.clone(), 50);
+        assert_eq!(space.reserved(&root, None), 150);
+        assert_eq!(space.reserved(&sub, None), 150);
+        std::fs::remove_dir(sub).unwrap();
+    }
+}
@@ -0,0 +1,255 @@
+use crate::config::DownloadConfig;
+use reqwest::{
+    Client, ClientBuilder,
+    dns::{Addrs, Name, Resolve, Resolving},
+    header::ACCEPT,
+};
+use std::{
+    fmt::{self, Debug},
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+use url::Url;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IpPreference {
+    #[default]
+    Auto,
+    Ipv4First,
+    Ipv6First,
+    Ipv4Only,
+    Ipv6Only,
+}
+
+impl IpPreference {
+    pub fn apply(self, addrs: impl Iterator<Item = SocketAddr>) -> Vec<SocketAddr> {
+        let mut addrs: Vec<_> = match self {
+            Self::Ipv4Only => addrs.filter(SocketAddr::is_ipv4).collect(),
+            Self::Ipv6Only => addrs.filter(SocketAddr::is_ipv6).collect(),
+            _ => addrs.collect(),
+        };
+        match self {
+            Self::Ipv4First => addrs.sort_by_key(SocketAddr::is_ipv6),
+            Self::Ipv6First => addrs.sort_by_key(SocketAddr::is_ipv4),
+            _ => {}
+        }
+        addrs
+    }
+}
+
+#[derive(Clone)]
+pub struct DnsResolver(Arc<dyn Resolve>);
+
+impl Debug for DnsResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DnsResolver")
+    }
+}
+
+impl DnsResolver {
+    pub fn new(resolver: Arc<dyn Resolve>) -> Self {
+        Self(resolver)
+    }
+    pub fn system() -> Self {
+        Self(Arc::new(SystemResolver))
+    }
+    /// Resolves through a DNS-over-HTTPS endpoint speaking the JSON API
+    /// (`application/dns-json`), e.g. `https://cloudflare-dns.com/dns-query`.
+    pub fn doh(endpoint: Url) -> Result<Self, reqwest::Error> {
+        Ok(Self(Arc::new(DohResolver {
+            endpoint,
+            client: Client::builder().build()?,
+        })))
+    }
+}
+
+struct SystemResolver;
+
+impl Resolve for SystemResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((host, 0)).await?;
+            Ok(Box::new(addrs) as Addrs)
+        })
+    }
+}
+
+struct DohResolver {
+    endpoint: Url,
+    client: Client,
+}
+
+impl Resolve for DohResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        let endpoint = self.endpoint.clone();
+        let client = self.client.clone();
+        Box::pin(async move {
+            let mut addrs = Vec::new();
+            for record_type in ["A", "AAAA"] {
+                let body = client
+                    .get(endpoint.clone())
+                    .query(&[("name", host.as_str()), ("type", record_type)])
+                    .header(ACCEPT, "application/dns-json")
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .bytes()
+                    .await?;
+                let body: serde_json::Value = serde_json::from_slice(&body)?;
+                let answers = body["Answer"].as_array().into_iter().flatten();
+                addrs.extend(
+                    answers
+                        .filter_map(|answer| answer["data"].as_str()?.parse::<IpAddr>().ok())
+                        .map(|ip| SocketAddr::new(ip, 0)),
+                );
+            }
+            if addrs.is_empty() {
+                return Err(format!("DoH lookup for {host} returned no addresses").into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+struct PreferenceResolver {
+    inner: Arc<dyn Resolve>,
+    preference: IpPreference,
+}
+
+impl Resolve for PreferenceResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolving = self.inner.resolve(name);
+        let preference = self.preference;
+        Box::pin(async move {
+            let addrs = preference.apply(resolving.await?);
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Parses a curl `--resolve` style entry: `host:port:addr[,addr]...`.
+pub fn parse_resolve_entry(entry: &str) -> Option<(&str, Vec<SocketAddr>)> {
+    let (host, rest) = entry.split_once(':')?;
+    let (port, addrs) = rest.split_once(':')?;
+    let port: u16 = port.parse().ok()?;
+    let addrs = addrs
+        .split(',')
+        .map(|addr| {
+            let addr = addr.trim().trim_start_matches('[').trim_end_matches(']');
+            Some(SocketAddr::new(addr.parse().ok()?, port))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    (!host.is_empty()).then_some((host, addrs))
+}
+
+#[derive(Debug, Clone)]
+pub struct DnsOptions {
+    pub resolve: Arc<[Arc<str>]>,
+    pub ip_preference: IpPreference,
+    pub resolver: DnsResolver,
+}
+
+impl DnsOptions {
+    pub fn new(config: &DownloadConfig) -> Self {
+        Self {
+            resolve: config.resolve.clone().unwrap(),
+            ip_preference: config.ip_preference.unwrap(),
+            resolver: config.dns_resolver.clone().unwrap(),
+        }
+    }
+
+    pub fn apply(&self, mut client: ClientBuilder) -> ClientBuilder {
+        for entry in self.resolve.iter() {
+            match parse_resolve_entry(entry) {
+                Some((host, addrs)) => client = client.resolve_to_addrs(host, &addrs),
+                None => log::error!("dns.apply(): Invalid resolve entry {entry:?}"),
+            }
+        }
+        client.dns_resolver(Arc::new(PreferenceResolver {
+            inner: self.resolver.0.clone(),
+            preference: self.ip_preference,
+        }))
+    }
+
+    /// Resolves `host` the way [`DnsOptions::apply`] makes reqwest do, for
+    /// connections made outside of it. As there, the port of `resolve`
+    /// entries is ignored in favour of `port`.
+    pub async fn lookup(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let addrs = if let Ok(ip) = host.parse::<IpAddr>() {
+            vec![SocketAddr::new(ip, 0)]
+        } else if let Some((_, addrs)) = self
+            .resolve
+            .iter()
+            .filter_map(|entry| parse_resolve_entry(entry))
+            .find(|(name, _)| name.eq_ignore_ascii_case(host))
+        {
+            addrs
+        } else {
+            let name = host
+                .parse::<Name>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            self.resolver
+                .0
+                .resolve(name)
+                .await
+                .map_err(io::Error::other)?
+                .collect()
+        };
+        let addrs = self.ip_preference.apply(addrs.into_iter());
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no address for {host}"),
+            ));
+        }
+        Ok(addrs
+            .into_iter()
+            .map(|addr| SocketAddr::new(addr.ip(), port))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_resolve_entry() {
+        let (host, addrs) = parse_resolve_entry("mirror.example.com:443:10.0.0.1,[::1]").unwrap();
+        assert_eq!(host, "mirror.example.com");
+        assert_eq!(
+            addrs,
+            [
+                "10.0.0.1:443".parse().unwrap(),
+                "[::1]:443".parse().unwrap()
+            ]
+        );
+        assert!(parse_resolve_entry("mirror.example.com:10.0.0.1").is_none());
+        assert!(parse_resolve_entry(":443:10.0.0.1").is_none());
+    }
+
+    #[test]
+    fn test_lookup() {
+        let options = DnsOptions {
+            resolve: Arc::from([Arc::from("mirror.example.com:443:10.0.0.1,[::1]")]),
+            ip_preference: IpPreference::Ipv6First,
+            resolver: DnsResolver::system(),
+        };
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let addrs = rt
+            .block_on(options.lookup("Mirror.example.com", 21))
+            .unwrap();
+        assert_eq!(
+            addrs,
+            ["[::1]:21".parse().unwrap(), "10.0.0.1:21".parse().unwrap()]
+        );
+        let addrs = rt.block_on(options.lookup("127.0.0.1", 21)).unwrap();
+        assert_eq!(addrs, ["127.0.0.1:21".parse().unwrap()]);
+    }
+}
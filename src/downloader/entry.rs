@@ -1,46 +1,127 @@
 use crate::{
+    allocation::{FileAllocation, allocate},
+    auth::{redact_text, redact_url},
+    checksum::Checksum,
+    conditional::{Recorded, Validators, set_modified},
     config::DownloadConfig,
+    control::{ControlFile, control_path},
+    disk_space::{DiskSpace, Reservation},
+    extract::{ArchiveFormat, ExtractEvent, extract},
+    file_conflict::{
+        ConflictOutcome, FileChanged, FileChangedAction, FileConflict, resolve_conflict,
+    },
+    hooks::{self, HookEvent, HookPayload},
     invert::invert_progress,
-    puller::{FastDownPuller, FastDownPullerOptions, build_client},
+    log_if_err::log_err,
+    puller::{
+        ClientOptions, FastDownPuller, FastDownPullerOptions, PullerError, build_client, prefetch,
+    },
+    safe_path::check_relative,
     send_err, send_err2,
-    unique_path::gen_unique_path,
+    tls::TlsError,
+    unique_path::{PathClaim, gen_unique_path, part_path},
 };
 use aria2_gid::Gid;
 use fast_down::{
     DownloadResult, Event, MergeProgress, ProgressEntry, UrlInfo,
     file::FilePusher,
-    http::{HttpError, Prefetch},
     multi::{self, TokioExecutor, download_multi},
     single::{self, EmptyExecutor, download_single},
 };
 use inherit_config::InheritAble;
 use kanal::{AsyncReceiver, AsyncSender};
-use reqwest::Client;
 use spin::mutex::SpinMutex;
-use std::{fmt::Debug, path::PathBuf, sync::Arc, time::Duration};
-use tokio::{fs::OpenOptions, task::JoinHandle};
+use std::{
+    fmt::{self, Debug},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    fs::{self, OpenOptions},
+    task::JoinHandle,
+};
 use url::Url;
 
 pub enum DownloadEvent {
     GetHttpClientError(reqwest::Error),
-    Prefetch(Result<Arc<UrlInfo>, (HttpError<Client>, Option<Duration>)>),
-    NoSameFile,
-    FilePath(tokio::io::Result<PathBuf>),
+    Prefetch(Result<Arc<UrlInfo>, (PullerError, Option<Duration>)>),
+    /// The remote file changed since the task started
+    NoSameFile(FileChangedAction),
+    FilePath(tokio::io::Result<(PathBuf, ConflictOutcome)>),
     CreatePullerError(reqwest::Error),
     CreatePusherError(std::io::Error),
-    Download(Event<HttpError<Client>, std::io::Error>),
+    Download(Event<PullerError, std::io::Error>),
+    LoadCookies(std::io::Result<usize>),
+    SaveCookies(std::io::Result<()>),
+    TlsConfigError(TlsError),
+    Checksum(std::io::Result<bool>),
+    Rename(std::io::Result<()>),
+    /// The task is paused until `required` bytes are free
+    DiskFull {
+        required: u64,
+        available: u64,
+    },
+    DiskSpaceFreed,
+    Extract(ExtractEvent),
+    /// A task in `depends_on` failed or was removed
+    DependencyFailed(Gid),
+    /// The task has stopped; nothing else is sent until it runs again
+    Finished,
+}
+
+impl DownloadEvent {
+    /// Describes events that end the task unsuccessfully, with credentials in
+    /// URLs masked.
+    pub fn error_message(&self) -> Option<String> {
+        let message = match self {
+            Self::GetHttpClientError(e) | Self::CreatePullerError(e) => Some(format!("{e:?}")),
+            Self::Prefetch(Err((e, _))) => Some(format!("{e:?}")),
+            Self::NoSameFile(FileChangedAction::Failed) => Some("remote file changed".into()),
+            Self::FilePath(Err(e)) | Self::CreatePusherError(e) | Self::Rename(Err(e)) => {
+                Some(format!("{e:?}"))
+            }
+            Self::TlsConfigError(e) => Some(format!("{e:?}")),
+            Self::Checksum(Err(e)) => Some(format!("{e:?}")),
+            Self::Checksum(Ok(false)) => Some("checksum mismatch".into()),
+            Self::Extract(ExtractEvent::Finish(Err(e))) => Some(format!("{e:?}")),
+            Self::DependencyFailed(gid) => Some(format!("prerequisite {gid} failed")),
+            _ => None,
+        };
+        message.map(|message| redact_text(&message))
+    }
 }
 
 #[derive(Debug)]
 pub struct DownloadEntryInner {
     pub url: Url,
+    pub mirrors: Vec<Url>,
+    pub checksum: Option<Checksum>,
+    pub out: Option<PathBuf>,
+    pub subdir: Option<PathBuf>,
     pub config: DownloadConfig,
     pub global_config: Arc<SpinMutex<DownloadConfig>>,
     pub info: Option<Arc<UrlInfo>>,
     pub push_progress: Vec<ProgressEntry>,
     pub path: Option<PathBuf>,
     pub event_chain: AsyncReceiver<DownloadEvent>,
+    pub disk: DiskSpace,
+    /// Why the last run failed
+    pub error: Option<String>,
+    pub complete: bool,
+    /// What the last run did about a changed remote file
+    pub file_changed: Option<FileChangedAction>,
+    /// What the last run did with the local file
+    pub outcome: Option<ConflictOutcome>,
+    /// Held while `path` may still be written
+    claim: Option<PathClaim>,
     is_running: bool,
+    paused: bool,
+    /// Set when the pullers should be rebuilt with the current config
+    restart: bool,
+    /// Bumped by every run, so that a superseded run leaves the state alone
+    generation: u64,
+    disk_full: bool,
     tx: AsyncSender<DownloadEvent>,
     download_result: Option<DownloadResultEnum>,
     handle: Option<JoinHandle<()>>,
@@ -55,12 +136,27 @@ impl DownloadEntryInner {
     pub fn is_running(&self) -> bool {
         self.is_running
     }
+    pub fn is_disk_full(&self) -> bool {
+        self.disk_full
+    }
+    /// Stops the running download so that it resumes with the current
+    /// config, keeping its progress. Returns `false` if nothing is being
+    /// downloaded or the server does not support resuming; the config then
+    /// applies from the next run.
+    pub fn restart(&mut self) -> bool {
+        if !self.info.as_ref().is_some_and(|info| info.fast_download) {
+            return false;
+        }
+        let Some(res) = self.download_result.take() else {
+            return false;
+        };
+        self.restart = true;
+        res.abort();
+        true
+    }
     pub fn abort(&mut self) {
         if let Some(res) = self.download_result.take() {
-            match res {
-                DownloadResultEnum::Single(r) => r.abort(),
-                DownloadResultEnum::Multiple(r) => r.abort(),
-            }
+            res.abort();
         } else if let Some(handle) = self.handle.take() {
             handle.abort();
         }
@@ -68,7 +164,7 @@ impl DownloadEntryInner {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct DownloadEntry {
     pub gid: Gid,
     pub inner: Arc<SpinMutex<DownloadEntryInner>>,
@@ -79,19 +175,34 @@ impl DownloadEntry {
         gid: Gid,
         option: AddOptions,
         global_config: Arc<SpinMutex<DownloadConfig>>,
+        disk: DiskSpace,
     ) -> Self {
         let (tx, event_chain) = kanal::unbounded_async();
         Self {
             gid,
             inner: Arc::new(SpinMutex::new(DownloadEntryInner {
                 url: option.url.clone(),
+                mirrors: option.mirrors.clone(),
+                checksum: option.checksum.clone(),
+                out: option.out.clone(),
+                subdir: option.subdir.clone(),
                 config: option.config.clone(),
                 global_config,
                 info: None,
                 push_progress: Vec::new(),
                 path: None,
                 event_chain,
+                disk,
+                error: None,
+                complete: false,
+                file_changed: None,
+                outcome: None,
+                claim: None,
                 is_running: false,
+                paused: false,
+                restart: false,
+                generation: 0,
+                disk_full: false,
                 tx,
                 download_result: None,
                 handle: None,
@@ -105,132 +216,773 @@ impl DownloadEntry {
     pub fn is_running(&self) -> bool {
         self.inner.lock().is_running
     }
+    pub fn is_disk_full(&self) -> bool {
+        self.inner.lock().disk_full
+    }
+    pub fn is_paused(&self) -> bool {
+        self.inner.lock().paused
+    }
+    /// Paused tasks are skipped by `Downloader::run`.
+    pub fn set_paused(&self, paused: bool) {
+        self.inner.lock().paused = paused;
+    }
+    pub fn is_complete(&self) -> bool {
+        self.inner.lock().complete
+    }
+    /// Complete, or left alone by its last run because the local file was
+    /// skipped or unchanged. `Downloader::run` does not start it again.
+    pub fn is_finished(&self) -> bool {
+        let inner = self.inner.lock();
+        inner.complete
+            || matches!(
+                inner.outcome,
+                Some(ConflictOutcome::Skipped { .. } | ConflictOutcome::Unchanged)
+            )
+    }
+    pub fn error(&self) -> Option<String> {
+        self.inner.lock().error.clone()
+    }
+    /// Marks the task as failed without running it.
+    pub fn fail_dependency(&self, prerequisite: Gid) {
+        let mut inner = self.inner.lock();
+        let event = DownloadEvent::DependencyFailed(prerequisite);
+        inner.error = event.error_message();
+        let _ = inner.tx.try_send(event);
+    }
+    /// `(size, downloaded)`; the size is `0` until the prefetch succeeds.
+    pub fn progress(&self) -> (u64, u64) {
+        let inner = self.inner.lock();
+        let size = inner.info.as_ref().map_or(0, |info| info.size);
+        (size, size - remaining(&inner.push_progress, size))
+    }
+    /// Overrides the fields set in `changes`, like aria2's `changeOption`. A
+    /// running download picks them up without losing progress.
+    pub fn change_options(&self, changes: &DownloadConfig) {
+        let mut inner = self.inner.lock();
+        inner.config = changes.inherit(&inner.config);
+        if inner.is_running && inner.restart() {
+            log::debug!("entry.change_options(): Restarting Gid {}", self.gid);
+        }
+    }
+    /// Points a stopped task at a new URL, e.g. a refreshed signed link. The
+    /// URL is prefetched first and rejected unless it serves the same file, so
+    /// that the existing progress can be kept.
+    pub async fn change_url(&self, url: Url, change: UrlChange) -> Result<(), ChangeUrlError> {
+        let (config, info) = {
+            let inner = self.inner.lock();
+            if inner.is_running {
+                return Err(ChangeUrlError::Running);
+            }
+            (inner.config(), inner.info.clone())
+        };
+        let client_options = ClientOptions::new(&config, &url).map_err(ChangeUrlError::Tls)?;
+        let client = build_client(&client_options).map_err(ChangeUrlError::Client)?;
+        let (new_info, _) = prefetch(&client, &client_options, url.clone())
+            .await
+            .map_err(|(e, _)| ChangeUrlError::Prefetch(e))?;
+        if let Some(info) = info
+            && (info.size != new_info.size || info.file_id != new_info.file_id)
+        {
+            log::debug!(
+                "entry.change_url(): {} serves {new_info:?}, expected {info:?}",
+                redact_url(&url)
+            );
+            return Err(ChangeUrlError::Mismatch);
+        }
+        let mut inner = self.inner.lock();
+        if inner.is_running {
+            return Err(ChangeUrlError::Running);
+        }
+        match change {
+            UrlChange::Replace => inner.url = url,
+            UrlChange::AddMirror if !inner.mirrors.contains(&url) => inner.mirrors.push(url),
+            UrlChange::AddMirror => {}
+        }
+        inner.error = None;
+        inner.paused = false;
+        Ok(())
+    }
     pub fn run(&self) -> Result<(), reqwest::Error> {
         let inner = self.inner.clone();
+        let gid = self.gid.to_string();
+        let mut guard = self.inner.lock();
+        guard.abort();
+        guard.is_running = true;
+        guard.generation += 1;
+        let generation = guard.generation;
+        drop(guard);
         let handle = tokio::spawn(async move {
             let mut guard = inner.lock();
-            guard.abort();
-            guard.is_running = true;
+            guard.error = None;
+            guard.complete = false;
+            guard.file_changed = None;
+            guard.outcome = None;
+            guard.restart = false;
             let config = guard.config();
             let url = guard.url.clone();
-            let tx = guard.tx.clone();
+            let mirrors = guard.mirrors.clone();
+            let checksum = guard.checksum.clone();
+            let out = guard.out.clone();
+            let subdir = guard.subdir.clone();
+            let disk = guard.disk.clone();
+            let events = guard.tx.clone();
             drop(guard);
-            let client = send_err2!(get_client(&config), tx, DownloadEvent::GetHttpClientError);
-            let (info, resp) = send_err!(
-                client.prefetch(url.clone()).await,
-                tx,
-                DownloadEvent::Prefetch
-            );
-            let info = Arc::new(info);
-            let mut guard = inner.lock();
-            if let Some(old_info) = guard.info.as_ref()
-                && old_info.file_id != info.file_id
-            {
-                tx.send(DownloadEvent::NoSameFile).await.unwrap();
-                return;
-            }
-            guard.info.replace(info.clone());
-            drop(guard);
-            tx.send(DownloadEvent::Prefetch(Ok(info.clone())))
-                .await
-                .unwrap();
-            let path = {
+            let hook_config = config.clone();
+            let payload = |event, path, error| HookPayload {
+                gid: gid.clone(),
+                event,
+                path,
+                error,
+            };
+            let (tx, relay) = kanal::unbounded_async();
+            let task_inner = inner.clone();
+            let task = async move {
+                let inner = task_inner;
+                let cookie_jar = config.cookie_jar.clone().unwrap();
+                let load_cookies = config.load_cookies.clone().unwrap();
+                if !load_cookies.as_os_str().is_empty() {
+                    tx.send(DownloadEvent::LoadCookies(
+                        cookie_jar.load(&load_cookies).await,
+                    ))
+                    .await
+                    .unwrap();
+                }
+                let task_url = url.clone();
+                let update = config.file_conflict.unwrap() == FileConflict::Update;
+                let (old_info, known_path, was_complete) = {
+                    let guard = inner.lock();
+                    let was_complete = guard.info.as_ref().is_some_and(|info| {
+                        invert_progress(&guard.push_progress, info.size).is_empty()
+                    });
+                    (guard.info.clone(), guard.path.clone(), was_complete)
+                };
+                let known_path = known_path.or_else(|| {
+                    let out = out.as_ref()?;
+                    let subdir = subdir.clone().unwrap_or_default();
+                    check_relative(out).ok()?;
+                    if !subdir.as_os_str().is_empty() {
+                        check_relative(&subdir).ok()?;
+                    }
+                    Some(config.save_dir.clone().unwrap().join(subdir).join(out))
+                });
+                let validators = match known_path.as_ref() {
+                    Some(path) if update => Validators::load(path, old_info.as_deref())
+                        .await
+                        .unwrap_or_else(|e| {
+                            log::error!("entry.run(): Cannot stat {path:?}: {e:?}");
+                            None
+                        }),
+                    _ => None,
+                };
+                let mut prefetched = None;
+                for url in std::iter::once(url).chain(mirrors) {
+                    let client_options = send_err2!(
+                        ClientOptions::new(&config, &url),
+                        tx,
+                        DownloadEvent::TlsConfigError
+                    );
+                    let shown = redact_url(&url);
+                    let mut conditional_res = None;
+                    if let Some(validators) = validators.as_ref()
+                        && let Some(path) = known_path.as_ref()
+                        && client_options.ftp.is_none()
+                        && let Some(options) = validators.conditional(&client_options)
+                    {
+                        let client = send_err2!(
+                            build_client(&options),
+                            tx,
+                            DownloadEvent::GetHttpClientError
+                        );
+                        match prefetch(&client, &options, url.clone()).await {
+                            Ok((_, Some(resp)))
+                                if resp.status() == reqwest::StatusCode::NOT_MODIFIED =>
+                            {
+                                log::debug!("entry.run(): {shown} not modified since {path:?}");
+                                inner.lock().path.replace(path.clone());
+                                tx.send(DownloadEvent::FilePath(Ok((
+                                    path.clone(),
+                                    ConflictOutcome::Unchanged,
+                                ))))
+                                .await
+                                .unwrap();
+                                return;
+                            }
+                            Ok(prefetched) => conditional_res = Some(Ok(prefetched)),
+                            Err(e) => {
+                                let e = redact_text(&format!("{e:?}"));
+                                log::debug!("entry.run(): Conditional prefetch {shown} failed: {e}")
+                            }
+                        }
+                    }
+                    let res = match conditional_res {
+                        Some(res) => res,
+                        None => {
+                            let client = send_err2!(
+                                build_client(&client_options),
+                                tx,
+                                DownloadEvent::GetHttpClientError
+                            );
+                            prefetch(&client, &client_options, url.clone()).await
+                        }
+                    };
+                    let is_ok = res.is_ok();
+                    if let Err(e) = &res {
+                        let e = redact_text(&format!("{e:?}"));
+                        log::debug!("entry.run(): Prefetch {shown} failed: {e}");
+                    }
+                    prefetched.replace((url, client_options, res));
+                    if is_ok {
+                        break;
+                    }
+                }
+                let (url, client_options, res) = prefetched.unwrap();
+                let (info, resp) = send_err!(res, tx, DownloadEvent::Prefetch);
+                let info = Arc::new(info);
                 let mut guard = inner.lock();
-                if let Some(path) = guard.path.as_ref() {
-                    path.clone()
+                let mut changed = None;
+                if !update
+                    && let Some(old_info) = guard.info.as_ref()
+                    && old_info.file_id != info.file_id
+                {
+                    let policy = config.file_changed.unwrap();
+                    let shown = redact_url(&url);
+                    log::debug!("entry.run(): {shown} changed since the last run, {policy:?}");
+                    if policy == FileChanged::Fail {
+                        guard.file_changed = Some(FileChangedAction::Failed);
+                        drop(guard);
+                        tx.send(DownloadEvent::NoSameFile(FileChangedAction::Failed))
+                            .await
+                            .unwrap();
+                        return;
+                    }
+                    guard.push_progress.clear();
+                    changed = Some(policy);
+                }
+                guard.info.replace(info.clone());
+                drop(guard);
+                tx.send(DownloadEvent::Prefetch(Ok(info.clone())))
+                    .await
+                    .unwrap();
+                let part_dir = config
+                    .part_file
+                    .unwrap()
+                    .then(|| config.part_dir.clone().unwrap());
+                let own_path = inner.lock().path.clone();
+                let (path, outcome) = if let Some(path) = own_path {
+                    (path, ConflictOutcome::Reused)
                 } else {
                     let mut path = config.save_dir.unwrap().to_path_buf();
-                    path.push(sanitize_filename::sanitize_with_options(
-                        &info.name,
-                        sanitize_filename::Options {
-                            windows: cfg!(windows),
-                            truncate: true,
-                            replacement: "_",
-                        },
-                    ));
-                    path = send_err!(gen_unique_path(path).await, tx, DownloadEvent::FilePath);
+                    if let Some(subdir) = subdir.as_ref() {
+                        send_err!(check_relative(subdir), tx, DownloadEvent::FilePath);
+                        path.push(subdir);
+                    }
+                    if let Some(out) = out.as_ref() {
+                        send_err!(check_relative(out), tx, DownloadEvent::FilePath);
+                        path.push(out);
+                    } else {
+                        path.push(sanitize_filename::sanitize_with_options(
+                            &info.name,
+                            sanitize_filename::Options {
+                                windows: cfg!(windows),
+                                truncate: true,
+                                replacement: "_",
+                            },
+                        ));
+                    }
+                    if let Some(parent) = path.parent() {
+                        send_err!(
+                            fs::create_dir_all(parent).await,
+                            tx,
+                            DownloadEvent::FilePath
+                        );
+                    }
+                    let (path, outcome, claim) = send_err!(
+                        resolve_conflict(
+                            path,
+                            config.file_conflict.unwrap(),
+                            info.size,
+                            info.fast_download,
+                            part_dir.as_deref(),
+                        )
+                        .await,
+                        tx,
+                        DownloadEvent::FilePath
+                    );
+                    let mut guard = inner.lock();
                     guard.path.replace(path.clone());
-                    path
+                    guard.claim = Some(claim);
+                    (path, outcome)
+                };
+                let write_path = if let Some(part_dir) = part_dir {
+                    if !part_dir.as_os_str().is_empty() {
+                        send_err!(
+                            fs::create_dir_all(&part_dir).await,
+                            tx,
+                            DownloadEvent::FilePath
+                        );
+                    }
+                    part_path(&path, &part_dir)
+                } else {
+                    path.clone()
+                };
+                let mut outcome = outcome;
+                if let Some(policy) = changed {
+                    let reused = outcome == ConflictOutcome::Reused;
+                    let action = match policy {
+                        FileChanged::KeepOld
+                            if reused && fs::try_exists(&write_path).await.unwrap_or(false) =>
+                        {
+                            let backup = send_err!(
+                                gen_unique_path(&write_path).await,
+                                tx,
+                                DownloadEvent::FilePath
+                            );
+                            send_err!(
+                                fs::rename(&write_path, &backup).await,
+                                tx,
+                                DownloadEvent::FilePath
+                            );
+                            FileChangedAction::KeptOld(backup)
+                        }
+                        _ => FileChangedAction::Restarted,
+                    };
+                    inner.lock().file_changed = Some(action.clone());
+                    tx.send(DownloadEvent::NoSameFile(action)).await.unwrap();
+                    if reused {
+                        outcome = ConflictOutcome::Overwritten;
+                    }
+                }
+                if update
+                    && (outcome == ConflictOutcome::Overwritten
+                        || outcome == ConflictOutcome::Reused && was_complete)
+                {
+                    let validators = Validators::load(&path, old_info.as_deref())
+                        .await
+                        .unwrap_or_default();
+                    outcome = if validators.is_some_and(|v| v.unchanged(&info)) {
+                        ConflictOutcome::Unchanged
+                    } else {
+                        ConflictOutcome::Overwritten
+                    };
+                }
+                if write_path != path {
+                    match outcome {
+                        ConflictOutcome::Resumed { .. } => {
+                            send_err!(
+                                fs::rename(&path, &write_path).await,
+                                tx,
+                                DownloadEvent::FilePath
+                            );
+                        }
+                        ConflictOutcome::New
+                            if config.file_conflict.unwrap() == FileConflict::Resume
+                                && info.fast_download =>
+                        {
+                            if let Ok(metadata) = fs::metadata(&write_path).await
+                                && metadata.len() <= info.size
+                            {
+                                outcome = ConflictOutcome::Resumed {
+                                    downloaded: metadata.len(),
+                                };
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                let control_path = control_path(&write_path);
+                let use_control = config.control_file.unwrap() && info.fast_download;
+                if use_control
+                    && matches!(
+                        outcome,
+                        ConflictOutcome::New | ConflictOutcome::Resumed { .. }
+                    )
+                    && fs::try_exists(&write_path).await.unwrap_or(false)
+                {
+                    match ControlFile::load(&control_path).await {
+                        Ok(Some(control)) if control.matches(&task_url, &info) => {
+                            let downloaded = control.progress.iter().map(|r| r.end - r.start).sum();
+                            inner.lock().push_progress = control.progress;
+                            outcome = ConflictOutcome::Restored { downloaded };
+                        }
+                        Ok(_) => {}
+                        Err(e) => log::error!("entry.run(): Cannot read {control_path:?}: {e:?}"),
+                    }
+                }
+                if matches!(outcome, ConflictOutcome::New | ConflictOutcome::Overwritten) {
+                    log_err(
+                        ControlFile::remove(&control_path).await,
+                        "entry.run(): Remove stale control file",
+                    );
+                }
+                if outcome == ConflictOutcome::Reused
+                    && invert_progress(&inner.lock().push_progress, info.size).is_empty()
+                {
+                    outcome = ConflictOutcome::Skipped { complete: true };
+                }
+                match outcome {
+                    ConflictOutcome::Overwritten => inner.lock().push_progress.clear(),
+                    ConflictOutcome::Resumed { downloaded } => {
+                        #[allow(clippy::single_range_in_vec_init)]
+                        let progress = vec![0..downloaded];
+                        inner.lock().push_progress = progress;
+                    }
+                    ConflictOutcome::Skipped { complete: true } | ConflictOutcome::Unchanged => {
+                        #[allow(clippy::single_range_in_vec_init)]
+                        let progress = vec![0..info.size];
+                        inner.lock().push_progress = progress;
+                    }
+                    _ => {}
+                }
+                tx.send(DownloadEvent::FilePath(Ok((path.clone(), outcome))))
+                    .await
+                    .unwrap();
+                if let ConflictOutcome::Skipped { .. } | ConflictOutcome::Unchanged = outcome {
+                    return;
+                }
+                let allocation = config.file_allocation.unwrap();
+                let check_interval = config.disk_check_interval.unwrap();
+                let min_free_space = config.min_free_space.unwrap();
+                let dir = match write_path.parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                    _ => PathBuf::from("."),
+                };
+                let reservation = disk.reserve(dir.clone(), 0);
+                let mut truncate =
+                    matches!(outcome, ConflictOutcome::New | ConflictOutcome::Overwritten);
+                let mut resp = resp;
+                let mut client_options = client_options;
+                let mut restarted = false;
+                let progress = loop {
+                    let config = inner.lock().config();
+                    if restarted {
+                        client_options = send_err2!(
+                            ClientOptions::new(&config, &url),
+                            tx,
+                            DownloadEvent::TlsConfigError
+                        );
+                    }
+                    let retry_gap = config.retry_gap.unwrap();
+                    let push_queue_cap = config.write_queue_cap.unwrap();
+                    let preallocated =
+                        matches!(allocation, FileAllocation::Falloc | FileAllocation::Full)
+                            && fs::metadata(&write_path)
+                                .await
+                                .is_ok_and(|m| !truncate && m.len() >= info.size);
+                    let required = if preallocated {
+                        0
+                    } else {
+                        remaining(&inner.lock().push_progress, info.size)
+                    };
+                    reservation.update(required);
+                    let space = SpaceCheck {
+                        dir: &dir,
+                        required,
+                        min_free_space,
+                        check_interval,
+                    };
+                    wait_for_space(&inner, &disk, &reservation, &tx, &space).await;
+                    let puller = send_err2!(
+                        FastDownPuller::new(FastDownPullerOptions {
+                            url: url.clone(),
+                            client_options: client_options.clone(),
+                            multiplexing: config.multiplexing.unwrap(),
+                            file_id: info.file_id.clone(),
+                            resp: resp.take().map(|resp| Arc::new(SpinMutex::new(Some(resp)))),
+                        }),
+                        tx,
+                        DownloadEvent::CreatePullerError
+                    );
+                    let mut file = send_err2!(
+                        OpenOptions::new()
+                            .write(true)
+                            .create(true)
+                            .truncate(truncate)
+                            .read(false)
+                            .open(&write_path)
+                            .await,
+                        tx,
+                        DownloadEvent::CreatePusherError
+                    );
+                    truncate = false;
+                    send_err2!(
+                        allocate(&mut file, info.size, allocation).await,
+                        tx,
+                        DownloadEvent::CreatePusherError
+                    );
+                    let pusher = send_err2!(
+                        FilePusher::new(file, info.size, config.write_buffer_size.unwrap()).await,
+                        tx,
+                        DownloadEvent::CreatePusherError
+                    );
+                    let res = if info.fast_download {
+                        let res = download_multi(
+                            puller,
+                            pusher,
+                            multi::DownloadOptions {
+                                download_chunks: invert_progress(
+                                    &inner.lock().push_progress,
+                                    info.size,
+                                ),
+                                concurrent: config.threads.unwrap(),
+                                min_chunk_size: config.min_chunk_size.unwrap(),
+                                retry_gap,
+                                push_queue_cap,
+                            },
+                        )
+                        .await;
+                        DownloadResultEnum::Multiple(res)
+                    } else {
+                        let res = download_single(
+                            puller,
+                            pusher,
+                            single::DownloadOptions {
+                                retry_gap,
+                                push_queue_cap,
+                            },
+                        )
+                        .await;
+                        DownloadResultEnum::Single(res)
+                    };
+                    inner.lock().download_result.replace(res.clone());
+                    let event_chain = match res {
+                        DownloadResultEnum::Single(res) => res.event_chain,
+                        DownloadResultEnum::Multiple(res) => res.event_chain,
+                    };
+                    let control_interval = config.control_interval.unwrap();
+                    let mut last_flush = Instant::now();
+                    let mut last_check = Instant::now();
+                    let mut out_of_space = false;
+                    while let Ok(event) = event_chain.recv().await {
+                        match &event {
+                            Event::PushProgress(_, range) => {
+                                let mut guard = inner.lock();
+                                guard.push_progress.merge_progress(range.clone());
+                                if use_control && last_flush.elapsed() >= control_interval {
+                                    let control = ControlFile::new(
+                                        &task_url,
+                                        &info,
+                                        guard.push_progress.clone(),
+                                    );
+                                    drop(guard);
+                                    log_err(
+                                        control.save(&control_path).await,
+                                        "entry.run(): Save control file",
+                                    );
+                                    last_flush = Instant::now();
+                                }
+                            }
+                            Event::PushError(_, e) | Event::FlushError(e)
+                                if e.kind() == std::io::ErrorKind::StorageFull =>
+                            {
+                                out_of_space = true;
+                            }
+                            _ => {}
+                        }
+                        if !out_of_space && last_check.elapsed() >= check_interval {
+                            last_check = Instant::now();
+                            let required = if preallocated {
+                                0
+                            } else {
+                                remaining(&inner.lock().push_progress, info.size)
+                            };
+                            reservation.update(required);
+                            // Pause while the rest still fits, not once a write fails
+                            out_of_space =
+                                disk.available(&dir, Some(&reservation))
+                                    .is_ok_and(|available| {
+                                        available < required.saturating_add(min_free_space)
+                                    });
+                        }
+                        if out_of_space && let Some(res) = inner.lock().download_result.take() {
+                            log::warn!("entry.run(): Pausing, {dir:?} is out of space");
+                            res.abort();
+                        }
+                        tx.send(DownloadEvent::Download(event)).await.unwrap();
+                    }
+                    let progress = inner.lock().push_progress.clone();
+                    let complete = invert_progress(&progress, info.size).is_empty();
+                    if use_control {
+                        let res = if complete {
+                            ControlFile::remove(&control_path).await
+                        } else {
+                            ControlFile::new(&task_url, &info, progress.clone())
+                                .save(&control_path)
+                                .await
+                        };
+                        log_err(res, "entry.run(): Update control file");
+                    }
+                    restarted = std::mem::take(&mut inner.lock().restart);
+                    if complete || !(out_of_space || restarted) {
+                        break progress;
+                    }
+                    if out_of_space && !info.fast_download {
+                        inner.lock().push_progress.clear();
+                        truncate = true;
+                    }
+                };
+                drop(reservation);
+                let complete = invert_progress(&progress, info.size).is_empty();
+                let mut verified = true;
+                if complete && let Some(checksum) = checksum {
+                    let res = checksum.verify(&write_path).await;
+                    verified = matches!(res, Ok(true));
+                    tx.send(DownloadEvent::Checksum(res)).await.unwrap();
+                }
+                let mut done = complete && verified;
+                let mut path = path;
+                if done
+                    && write_path != path
+                    && config.file_conflict.unwrap() == FileConflict::Rename
+                    && fs::try_exists(&path).await.unwrap_or(false)
+                {
+                    // Something else created the file while this one downloaded
+                    path = send_err!(gen_unique_path(&path).await, tx, DownloadEvent::Rename);
+                    inner.lock().path.replace(path.clone());
+                }
+                if done && write_path != path {
+                    let res = fs::rename(&write_path, &path).await;
+                    done = res.is_ok();
+                    tx.send(DownloadEvent::Rename(res)).await.unwrap();
+                }
+                if done && update {
+                    log_err(
+                        set_modified(&path, &info).await,
+                        "entry.run(): Set modification time",
+                    );
+                    log_err(
+                        Recorded::new(&info).save(&path).await,
+                        "entry.run(): Save validators",
+                    );
+                }
+                if done
+                    && config.extract.unwrap()
+                    && let Some((format, stem)) = ArchiveFormat::detect(&path)
+                {
+                    let extract_dir = config.extract_dir.clone().unwrap();
+                    let dest = if extract_dir.as_os_str().is_empty() {
+                        path.with_file_name(stem)
+                    } else {
+                        extract_dir.to_path_buf()
+                    };
+                    tx.send(DownloadEvent::Extract(ExtractEvent::Start {
+                        archive: path.clone(),
+                        dest: dest.clone(),
+                    }))
+                    .await
+                    .unwrap();
+                    let max_size = config.extract_max_size.unwrap();
+                    let (archive, progress_tx) = (path.clone(), tx.clone());
+                    let res = tokio::task::spawn_blocking(move || {
+                        extract(&archive, &dest, format, max_size, |entries, bytes| {
+                            let event = ExtractEvent::Progress { entries, bytes };
+                            let _ = progress_tx.try_send(DownloadEvent::Extract(event));
+                        })
+                    })
+                    .await
+                    .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+                    let extracted = res.is_ok();
+                    tx.send(DownloadEvent::Extract(ExtractEvent::Finish(res)))
+                        .await
+                        .unwrap();
+                    if extracted && config.extract_remove_archive.unwrap() {
+                        tx.send(DownloadEvent::Extract(ExtractEvent::RemoveArchive(
+                            fs::remove_file(&path).await,
+                        )))
+                        .await
+                        .unwrap();
+                    }
+                }
+                let save_cookies = config.save_cookies.unwrap();
+                if !save_cookies.as_os_str().is_empty() {
+                    tx.send(DownloadEvent::SaveCookies(
+                        cookie_jar.save(&save_cookies).await,
+                    ))
+                    .await
+                    .unwrap();
                 }
             };
-            tx.send(DownloadEvent::FilePath(Ok(path.clone())))
-                .await
-                .unwrap();
-            let puller = send_err2!(
-                FastDownPuller::new(FastDownPullerOptions {
-                    url,
-                    headers: config.headers.unwrap(),
-                    proxy: &config.proxy.unwrap(),
-                    multiplexing: config.multiplexing.unwrap(),
-                    accept_invalid_certs: config.accept_invalid_certs.unwrap(),
-                    accept_invalid_hostnames: config.accept_invalid_hostnames.unwrap(),
-                    file_id: info.file_id.clone(),
-                    resp: Some(Arc::new(SpinMutex::new(Some(resp)))),
-                }),
-                tx,
-                DownloadEvent::CreatePullerError
-            );
-            let retry_gap = config.retry_gap.unwrap();
-            let push_queue_cap = config.write_queue_cap.unwrap();
-            let file = send_err2!(
-                OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .truncate(false)
-                    .read(false)
-                    .open(&path)
-                    .await,
-                tx,
-                DownloadEvent::CreatePusherError
-            );
-            let pusher = send_err2!(
-                FilePusher::new(file, info.size, config.write_buffer_size.unwrap()).await,
-                tx,
-                DownloadEvent::CreatePusherError
-            );
-            let res = if info.fast_download {
-                let res = download_multi(
-                    puller,
-                    pusher,
-                    multi::DownloadOptions {
-                        download_chunks: invert_progress(&inner.lock().push_progress, info.size),
-                        concurrent: config.threads.unwrap(),
-                        min_chunk_size: config.min_chunk_size.unwrap(),
-                        retry_gap,
-                        push_queue_cap,
-                    },
-                )
-                .await;
-                DownloadResultEnum::Multiple(res)
-            } else {
-                let res = download_single(
-                    puller,
-                    pusher,
-                    single::DownloadOptions {
-                        retry_gap,
-                        push_queue_cap,
-                    },
-                )
-                .await;
-                DownloadResultEnum::Single(res)
-            };
-            inner.lock().download_result.replace(res.clone());
-            let event_chain = match res {
-                DownloadResultEnum::Single(res) => res.event_chain,
-                DownloadResultEnum::Multiple(res) => res.event_chain,
-            };
-            while let Ok(event) = event_chain.recv().await {
-                if let Event::PushProgress(_, range) = &event {
-                    inner.lock().push_progress.merge_progress(range.clone());
+            let forward = async {
+                let mut error = None;
+                let mut unchanged = false;
+                while let Ok(event) = relay.recv().await {
+                    if let DownloadEvent::FilePath(Ok((_, outcome))) = &event {
+                        inner.lock().outcome = Some(*outcome);
+                    }
+                    match &event {
+                        DownloadEvent::FilePath(Ok((_, ConflictOutcome::Unchanged))) => {
+                            unchanged = true;
+                        }
+                        DownloadEvent::FilePath(Ok((path, outcome)))
+                            if !matches!(outcome, ConflictOutcome::Skipped { .. }) =>
+                        {
+                            hooks::fire(
+                                &hook_config,
+                                payload(HookEvent::Start, Some(path.clone()), None),
+                            );
+                        }
+                        DownloadEvent::DiskFull { .. } => {
+                            let path = inner.lock().path.clone();
+                            hooks::fire(&hook_config, payload(HookEvent::Pause, path, None));
+                        }
+                        event => {
+                            if let Some(e) = event.error_message() {
+                                error = Some(e);
+                            }
+                        }
+                    }
+                    events.send(event).await.unwrap();
                 }
-                tx.send(DownloadEvent::Download(event)).await.unwrap();
+                (error, unchanged)
+            };
+            let ((), (error, unchanged)) = futures_util::join!(task, forward);
+            let mut guard = inner.lock();
+            if guard.generation != generation {
+                return;
             }
+            guard.is_running = false;
+            guard.download_result = None;
+            guard.handle = None;
+            let path = guard.path.clone();
+            let complete =
+                guard.info.as_ref().is_some_and(|info| {
+                    invert_progress(&guard.push_progress, info.size).is_empty()
+                }) || unchanged;
+            guard.complete = complete && error.is_none();
+            if guard.complete {
+                guard.claim = None;
+            }
+            guard.error = error.clone();
+            drop(guard);
+            if error.is_some() {
+                hooks::fire(&hook_config, payload(HookEvent::Error, path, error));
+            } else if complete {
+                hooks::fire(&hook_config, payload(HookEvent::Complete, path, None));
+            }
+            events.send(DownloadEvent::Finished).await.unwrap();
         });
         self.inner.lock().handle.replace(handle);
         Ok(())
     }
 }
 
+/// Shows the options with credentials masked, see [`AddOptions::redacted`].
+impl Debug for DownloadEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("DownloadEntry");
+        s.field("gid", &self.gid)
+            .field("add_options", &self.add_options.redacted());
+        if let Some(inner) = self.inner.try_lock() {
+            s.field("url", &redact_url(&inner.url))
+                .field("path", &inner.path)
+                .field("is_running", &inner.is_running)
+                .field("paused", &inner.paused)
+                .field("complete", &inner.complete)
+                .field("error", &inner.error);
+        }
+        s.finish_non_exhaustive()
+    }
+}
+
 impl PartialEq for DownloadEntry {
     fn eq(&self, other: &Self) -> bool {
         self.gid == other.gid
@@ -243,30 +995,208 @@ impl PartialEq<Gid> for DownloadEntry {
     }
 }
 
+#[derive(Debug)]
+pub enum ChangeUrlError {
+    NotFound,
+    Running,
+    Tls(TlsError),
+    Client(reqwest::Error),
+    Prefetch(PullerError),
+    /// The new URL serves a different file than the one being downloaded
+    Mismatch,
+}
+
+/// How [`DownloadEntry::change_url`] applies the new URL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UrlChange {
+    Replace,
+    AddMirror,
+}
+
 #[derive(Debug, Clone)]
 pub struct AddOptions {
     pub url: Url,
+    /// Tried in order when prefetching `url` fails
+    pub mirrors: Vec<Url>,
     pub immediate_download: bool,
     pub config: DownloadConfig,
+    pub checksum: Option<Checksum>,
+    /// File name relative to `save_dir`, replacing the one from the server
+    pub out: Option<PathBuf>,
+    /// Directory relative to `save_dir`, created if missing
+    pub subdir: Option<PathBuf>,
+    /// Tasks that must complete before this one starts
+    pub depends_on: Vec<Gid>,
+    pub on_dependency_error: DependencyPolicy,
 }
 
-pub fn get_client(config: &DownloadConfig) -> Result<Client, reqwest::Error> {
-    build_client(
-        config.headers.as_ref().unwrap(),
-        config.proxy.as_ref().unwrap(),
-        config.accept_invalid_certs.unwrap(),
-        config.accept_invalid_hostnames.unwrap(),
-    )
+impl AddOptions {
+    /// A copy for logs, with passwords in URLs and credentials in the config
+    /// masked.
+    pub fn redacted(&self) -> Self {
+        Self {
+            url: redact_url(&self.url),
+            mirrors: self.mirrors.iter().map(redact_url).collect(),
+            config: self.config.redacted(),
+            ..self.clone()
+        }
+    }
+}
+
+/// What happens to a task when one of its prerequisites fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DependencyPolicy {
+    /// Keep the task, marked as failed
+    #[default]
+    Fail,
+    /// Remove the task from the downloader
+    Cancel,
 }
 
 #[derive(Debug, Clone)]
 pub enum DownloadResultEnum {
-    Single(DownloadResult<EmptyExecutor, HttpError<Client>, std::io::Error>),
+    Single(DownloadResult<EmptyExecutor, PullerError, std::io::Error>),
     Multiple(
-        DownloadResult<
-            TokioExecutor<FastDownPuller, std::io::Error>,
-            HttpError<Client>,
-            std::io::Error,
-        >,
+        DownloadResult<TokioExecutor<FastDownPuller, std::io::Error>, PullerError, std::io::Error>,
     ),
 }
+
+impl DownloadResultEnum {
+    pub fn abort(&self) {
+        match self {
+            DownloadResultEnum::Single(r) => r.abort(),
+            DownloadResultEnum::Multiple(r) => r.abort(),
+        }
+    }
+}
+
+fn remaining(progress: &[ProgressEntry], size: u64) -> u64 {
+    let downloaded: u64 = progress.iter().map(|r| r.end - r.start).sum();
+    size.saturating_sub(downloaded)
+}
+
+struct SpaceCheck<'a> {
+    dir: &'a Path,
+    required: u64,
+    min_free_space: u64,
+    check_interval: Duration,
+}
+
+/// Waits until `dir` has room for `required` more bytes on top of
+/// `min_free_space`, marking the task as `disk_full` in the meantime.
+async fn wait_for_space(
+    inner: &SpinMutex<DownloadEntryInner>,
+    disk: &DiskSpace,
+    reservation: &Reservation,
+    tx: &AsyncSender<DownloadEvent>,
+    space: &SpaceCheck<'_>,
+) {
+    let SpaceCheck {
+        dir,
+        required,
+        min_free_space,
+        check_interval,
+    } = *space;
+    loop {
+        match disk.available(dir, Some(reservation)) {
+            Ok(available) if available < required.saturating_add(min_free_space) => {
+                if !std::mem::replace(&mut inner.lock().disk_full, true) {
+                    log::warn!(
+                        "entry.run(): {dir:?} has {available} bytes free, {required} needed"
+                    );
+                    tx.send(DownloadEvent::DiskFull {
+                        required,
+                        available,
+                    })
+                    .await
+                    .unwrap();
+                }
+                tokio::time::sleep(check_interval).await;
+            }
+            Ok(_) => break,
+            Err(e) => {
+                log::error!("entry.run(): Cannot query free space in {dir:?}: {e:?}");
+                break;
+            }
+        }
+    }
+    if std::mem::take(&mut inner.lock().disk_full) {
+        tx.send(DownloadEvent::DiskSpaceFreed).await.unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// Answers every request with the same five bytes.
+    async fn serve(listener: TcpListener) {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buf = vec![0; 4096];
+                let _ = stream.read(&mut buf).await;
+                let _ = stream
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nAccept-Ranges: bytes\r\n\
+                          Connection: close\r\n\r\nhello",
+                    )
+                    .await;
+            });
+        }
+    }
+
+    #[test]
+    fn test_change_url_after_failure() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let dead =
+                Url::parse(&format!("http://{}/file", closed.local_addr().unwrap())).unwrap();
+            drop(closed);
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let live =
+                Url::parse(&format!("http://{}/file", listener.local_addr().unwrap())).unwrap();
+            tokio::spawn(serve(listener));
+
+            let entry = DownloadEntry::new(
+                Gid::new(),
+                AddOptions {
+                    url: dead,
+                    mirrors: Vec::new(),
+                    immediate_download: true,
+                    config: DownloadConfig::empty(),
+                    checksum: None,
+                    out: None,
+                    subdir: None,
+                    depends_on: Vec::new(),
+                    on_dependency_error: DependencyPolicy::Fail,
+                },
+                Arc::new(SpinMutex::new(DownloadConfig::default())),
+                DiskSpace::new(),
+            );
+            let events = entry.inner.lock().event_chain.clone();
+            entry.run().unwrap();
+            while let Ok(event) = events.recv().await {
+                if let DownloadEvent::Finished = event {
+                    break;
+                }
+            }
+            assert!(!entry.is_running());
+            assert!(entry.error().is_some());
+
+            entry
+                .change_url(live.clone(), UrlChange::Replace)
+                .await
+                .unwrap();
+            assert_eq!(entry.inner.lock().url, live);
+            assert!(entry.error().is_none());
+        });
+    }
+}
@@ -0,0 +1,279 @@
+use crate::safe_path::check_relative;
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+const SUFFIXES: [(&str, ArchiveFormat); 6] = [
+    (".tar.gz", ArchiveFormat::TarGz),
+    (".tgz", ArchiveFormat::TarGz),
+    (".tar.zst", ArchiveFormat::TarZst),
+    (".tzst", ArchiveFormat::TarZst),
+    (".tar", ArchiveFormat::Tar),
+    (".zip", ArchiveFormat::Zip),
+];
+
+impl ArchiveFormat {
+    /// Detects the format from the file name, returning it together with the
+    /// name stripped of the archive suffix.
+    pub fn detect(path: &Path) -> Option<(Self, String)> {
+        let name = path.file_name()?.to_str()?;
+        let lower = name.to_ascii_lowercase();
+        SUFFIXES.iter().find_map(|(suffix, format)| {
+            (lower.len() > suffix.len() && lower.ends_with(suffix))
+                .then(|| (*format, name[..name.len() - suffix.len()].to_string()))
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum ExtractEvent {
+    Start {
+        archive: PathBuf,
+        dest: PathBuf,
+    },
+    Progress {
+        entries: u64,
+        bytes: u64,
+    },
+    /// Total bytes extracted
+    Finish(io::Result<u64>),
+    RemoveArchive(io::Result<()>),
+}
+
+struct Extractor<'a, F> {
+    dest: &'a Path,
+    /// `0` means unlimited
+    max_size: u64,
+    entries: u64,
+    bytes: u64,
+    progress: F,
+}
+
+/// The archive root, as in the `./` entry of `tar -C dir -cf x.tar .`
+fn is_root(name: &Path) -> bool {
+    name.components().all(|c| c == Component::CurDir)
+}
+
+impl<F: FnMut(u64, u64)> Extractor<'_, F> {
+    fn target(&self, name: &Path) -> io::Result<PathBuf> {
+        check_relative(name)?;
+        Ok(self.dest.join(name))
+    }
+
+    fn dir(&self, name: &Path) -> io::Result<()> {
+        fs::create_dir_all(self.target(name)?)
+    }
+
+    fn file(&mut self, name: &Path, mut reader: impl Read) -> io::Result<()> {
+        let path = self.target(name)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = File::create(path)?;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            self.bytes += n as u64;
+            if self.max_size != 0 && self.bytes > self.max_size {
+                return Err(io::Error::other(format!(
+                    "archive expands beyond {} bytes",
+                    self.max_size
+                )));
+            }
+            file.write_all(&buf[..n])?;
+        }
+        self.entries += 1;
+        (self.progress)(self.entries, self.bytes);
+        Ok(())
+    }
+
+    fn zip(&mut self, archive: File) -> io::Result<()> {
+        let mut zip = zip::ZipArchive::new(archive).map_err(io::Error::other)?;
+        for i in 0..zip.len() {
+            let entry = zip.by_index(i).map_err(io::Error::other)?;
+            let name = PathBuf::from(entry.name());
+            if is_root(&name) {
+                continue;
+            }
+            if entry.is_dir() {
+                self.dir(&name)?;
+            } else if entry.is_symlink() {
+                log::warn!("extract.zip(): Skipping symlink {name:?}");
+            } else {
+                self.file(&name, entry)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn tar(&mut self, reader: impl Read) -> io::Result<()> {
+        let mut tar = tar::Archive::new(reader);
+        for entry in tar.entries()? {
+            let entry = entry?;
+            let name = entry.path()?.into_owned();
+            let kind = entry.header().entry_type();
+            if is_root(&name) {
+                continue;
+            }
+            if kind.is_dir() {
+                self.dir(&name)?;
+            } else if kind.is_file() {
+                self.file(&name, entry)?;
+            } else {
+                log::warn!("extract.tar(): Skipping {kind:?} entry {name:?}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Extracts `archive` into `dest`, rejecting entries that would land outside
+/// of it and stopping once more than `max_size` bytes (`0` = unlimited) were
+/// written. `progress` receives the entry count and bytes so far.
+pub fn extract(
+    archive: &Path,
+    dest: &Path,
+    format: ArchiveFormat,
+    max_size: u64,
+    progress: impl FnMut(u64, u64),
+) -> io::Result<u64> {
+    fs::create_dir_all(dest)?;
+    let file = File::open(archive)?;
+    let mut extractor = Extractor {
+        dest,
+        max_size,
+        entries: 0,
+        bytes: 0,
+        progress,
+    };
+    match format {
+        ArchiveFormat::Zip => extractor.zip(file)?,
+        ArchiveFormat::Tar => extractor.tar(file)?,
+        ArchiveFormat::TarGz => extractor.tar(flate2::read::GzDecoder::new(file))?,
+        ArchiveFormat::TarZst => extractor.tar(zstd::stream::read::Decoder::new(file)?)?,
+    }
+    Ok(extractor.bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_archive_format() {
+        assert_eq!(
+            ArchiveFormat::detect(Path::new("/tmp/Bundle.TAR.GZ")),
+            Some((ArchiveFormat::TarGz, "Bundle".into()))
+        );
+        assert_eq!(
+            ArchiveFormat::detect(Path::new("data.tar.zst")),
+            Some((ArchiveFormat::TarZst, "data".into()))
+        );
+        assert_eq!(
+            ArchiveFormat::detect(Path::new("a.zip")),
+            Some((ArchiveFormat::Zip, "a".into()))
+        );
+        assert_eq!(ArchiveFormat::detect(Path::new(".zip")), None);
+        assert_eq!(ArchiveFormat::detect(Path::new("a.iso")), None);
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes the name raw, since `tar::Builder` refuses `..` itself.
+    fn tar_entry(
+        builder: &mut tar::Builder<Vec<u8>>,
+        name: &str,
+        kind: tar::EntryType,
+        data: &[u8],
+    ) {
+        let mut header = tar::Header::new_old();
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_entry_type(kind);
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append(&header, data).unwrap();
+    }
+
+    fn tar_archive(entries: &[(&str, tar::EntryType, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for &(name, kind, data) in entries {
+            tar_entry(&mut builder, name, kind, data);
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_extract_tar() {
+        let dir = temp_dir("extract-tar");
+        let archive = dir.join("a.tar");
+        fs::write(
+            &archive,
+            tar_archive(&[
+                ("./", tar::EntryType::Directory, b""),
+                ("./sub/", tar::EntryType::Directory, b""),
+                ("./sub/b.txt", tar::EntryType::Regular, b"hello"),
+            ]),
+        )
+        .unwrap();
+        let dest = dir.join("out");
+        let bytes = extract(&archive, &dest, ArchiveFormat::Tar, 0, |_, _| {}).unwrap();
+        assert_eq!(bytes, 5);
+        assert_eq!(fs::read(dest.join("sub/b.txt")).unwrap(), b"hello");
+
+        fs::write(
+            &archive,
+            tar_archive(&[("../evil.txt", tar::EntryType::Regular, b"x")]),
+        )
+        .unwrap();
+        assert!(extract(&archive, &dest, ArchiveFormat::Tar, 0, |_, _| {}).is_err());
+        assert!(!dir.join("evil.txt").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_extract_zip() {
+        use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+        let zip_archive = |entries: &[(&str, &[u8])]| {
+            let mut writer = ZipWriter::new(io::Cursor::new(Vec::new()));
+            let options =
+                SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+            for (name, data) in entries {
+                writer.start_file(*name, options).unwrap();
+                writer.write_all(data).unwrap();
+            }
+            writer.finish().unwrap().into_inner()
+        };
+        let dir = temp_dir("extract-zip");
+        let archive = dir.join("a.zip");
+        fs::write(&archive, zip_archive(&[("sub/b.txt", b"hello")])).unwrap();
+        let dest = dir.join("out");
+        let bytes = extract(&archive, &dest, ArchiveFormat::Zip, 0, |_, _| {}).unwrap();
+        assert_eq!(bytes, 5);
+        assert_eq!(fs::read(dest.join("sub/b.txt")).unwrap(), b"hello");
+
+        fs::write(&archive, zip_archive(&[("../evil.txt", b"x")])).unwrap();
+        assert!(extract(&archive, &dest, ArchiveFormat::Zip, 0, |_, _| {}).is_err());
+        assert!(!dir.join("evil.txt").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+use crate::unique_path::{PathClaim, claim_unique_path};
+use std::path::{Path, PathBuf};
+use tokio::{fs, io};
+
+/// What to do when the target file already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileConflict {
+    /// Append ` (N)` to the file name
+    #[default]
+    Rename,
+    Overwrite,
+    /// Leave the file alone; the task counts as complete if the size matches
+    Skip,
+    /// Treat the existing bytes as an already downloaded prefix
+    Resume,
+    Fail,
+    /// Download again only if the remote file changed since the local copy
+    Update,
+}
+
+/// What to do when the remote file no longer matches the one a task started
+/// downloading, i.e. its `file_id` changed between runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileChanged {
+    #[default]
+    Fail,
+    /// Discard the progress and download again from zero
+    Restart,
+    /// Move the old partial file aside and start a fresh one
+    KeepOld,
+}
+
+/// The decision taken for [`FileChanged`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileChangedAction {
+    Failed,
+    Restarted,
+    /// Where the old partial file was moved
+    KeptOld(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictOutcome {
+    New,
+    /// The task's own file from an earlier run
+    Reused,
+    Renamed,
+    Overwritten,
+    Skipped {
+        complete: bool,
+    },
+    Resumed {
+        downloaded: u64,
+    },
+    /// Progress taken from the control file of an interrupted run
+    Restored {
+        downloaded: u64,
+    },
+    /// The local copy matches the remote file and was kept
+    Unchanged,
+}
+
+/// `resumable` tells whether the server accepts range requests, `part_dir`
+/// is where `.part` files go, or `None` without them. The returned claim keeps
+/// other tasks of this process off the path while the download is pending.
+pub async fn resolve_conflict(
+    path: PathBuf,
+    policy: FileConflict,
+    size: u64,
+    resumable: bool,
+    part_dir: Option<&Path>,
+) -> io::Result<(PathBuf, ConflictOutcome, PathClaim)> {
+    if policy == FileConflict::Rename {
+        let (unique, claim) = claim_unique_path(&path, part_dir).await?;
+        let outcome = if unique == path {
+            ConflictOutcome::New
+        } else {
+            ConflictOutcome::Renamed
+        };
+        return Ok((unique, outcome, claim));
+    }
+    let claim = PathClaim::new(&path).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{path:?} is the target of another task"),
+        )
+    })?;
+    let len = match fs::metadata(&path).await {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok((path, ConflictOutcome::New, claim));
+        }
+        Err(e) => return Err(e),
+    };
+    let outcome = match policy {
+        FileConflict::Rename => unreachable!(),
+        FileConflict::Overwrite | FileConflict::Update => ConflictOutcome::Overwritten,
+        FileConflict::Skip => ConflictOutcome::Skipped {
+            complete: len == size,
+        },
+        FileConflict::Resume if len == size => ConflictOutcome::Skipped { complete: true },
+        FileConflict::Resume if resumable && len < size => {
+            ConflictOutcome::Resumed { downloaded: len }
+        }
+        FileConflict::Resume => ConflictOutcome::Overwritten,
+        FileConflict::Fail => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{path:?} already exists"),
+            ));
+        }
+    };
+    Ok((path, outcome, claim))
+}
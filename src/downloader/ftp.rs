@@ -0,0 +1,618 @@
+use crate::{
+    auth::{Secret, netrc_entry},
+    config::DownloadConfig,
+    dns::DnsOptions,
+    tls::{self, TlsError},
+};
+use bytes::Bytes;
+use fast_down::{FileId, UrlInfo};
+use futures_util::{Stream, stream};
+use percent_encoding::percent_decode_str;
+use rustls::{ClientConfig, pki_types::ServerName};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use time::{Date, Month, PrimitiveDateTime, Time};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf},
+    net::{TcpListener, TcpStream},
+};
+use tokio_rustls::{TlsConnector, client::TlsStream};
+use url::Url;
+
+#[derive(Debug)]
+pub enum FtpError {
+    Io(io::Error),
+    /// The server rejected `command`
+    Reply {
+        command: String,
+        code: u16,
+        message: String,
+    },
+    InvalidUrl(Url),
+    /// A path or credential contains CR, LF or NUL, which would end the
+    /// command early and smuggle in another one
+    UnsafeArgument,
+}
+
+impl From<io::Error> for FtpError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+pub fn is_ftp(url: &Url) -> bool {
+    matches!(url.scheme(), "ftp" | "ftps")
+}
+
+#[derive(Debug, Clone)]
+pub struct FtpOptions {
+    pub user: Arc<str>,
+    pub passwd: Secret,
+    pub passive: bool,
+    /// Set for `ftps://`
+    pub tls: Option<Arc<ClientConfig>>,
+    pub dns: DnsOptions,
+}
+
+impl FtpOptions {
+    /// Precedence: credentials in the URL, then `ftp_user`/`ftp_passwd`, then
+    /// `.netrc`, then anonymous.
+    pub fn new(config: &DownloadConfig, url: &Url) -> Result<Self, TlsError> {
+        let decode = |s: &str| percent_decode_str(s).decode_utf8_lossy().into_owned();
+        let user = config.ftp_user.clone().unwrap();
+        let (user, passwd): (Arc<str>, Secret) = if !url.username().is_empty() {
+            let passwd = url.password().map(decode).unwrap_or_default();
+            (decode(url.username()).into(), passwd.into())
+        } else if !user.is_empty() {
+            (user, config.ftp_passwd.clone().unwrap())
+        } else if let Some(entry) = url.host_str().and_then(|host| netrc_entry(config, host)) {
+            (entry.login, entry.password)
+        } else {
+            (Arc::from("anonymous"), Secret::from("anonymous@"))
+        };
+        Ok(Self {
+            user,
+            passwd,
+            passive: config.ftp_passive.unwrap(),
+            tls: match url.scheme() {
+                "ftps" => Some(Arc::new(tls::build_config(config)?)),
+                _ => None,
+            },
+            dns: DnsOptions::new(config),
+        })
+    }
+}
+
+enum Conn {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for Conn {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Tls(stream) => Pin::new(&mut **stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Conn {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Tls(stream) => Pin::new(&mut **stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Tls(stream) => Pin::new(&mut **stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Tls(stream) => Pin::new(&mut **stream).poll_shutdown(cx),
+        }
+    }
+}
+
+fn malformed(reply: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("malformed reply {reply:?}"),
+    )
+}
+
+/// Reads a possibly multi-line reply, returning its code and text.
+async fn read_reply(control: &mut BufReader<Conn>) -> Result<(u16, String), FtpError> {
+    let mut line = String::new();
+    if control.read_line(&mut line).await? == 0 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    let code: u16 = line
+        .get(..3)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| malformed(&line))?;
+    let mut message = line[3..]
+        .trim_start_matches(['-', ' '])
+        .trim_end()
+        .to_string();
+    if line.as_bytes().get(3) == Some(&b'-') {
+        let last = format!("{code} ");
+        loop {
+            line.clear();
+            if control.read_line(&mut line).await? == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            message.push('\n');
+            message.push_str(line.trim_end());
+            if line.starts_with(&last) {
+                break;
+            }
+        }
+    }
+    Ok((code, message))
+}
+
+/// Values are percent-decoded from the URL, so they may hold line breaks.
+fn check_command(command: &str) -> Result<(), FtpError> {
+    if command.contains(['\r', '\n', '\0']) {
+        return Err(FtpError::UnsafeArgument);
+    }
+    Ok(())
+}
+
+/// Sends `command` (nothing if empty) and fails unless the reply code is one
+/// of `expected`.
+async fn command(
+    control: &mut BufReader<Conn>,
+    command: &str,
+    expected: &[u16],
+) -> Result<(u16, String), FtpError> {
+    check_command(command)?;
+    if !command.is_empty() {
+        match command.strip_prefix("PASS ") {
+            Some(_) => log::debug!("ftp.command(): PASS ***"),
+            None => log::debug!("ftp.command(): {command}"),
+        }
+        let conn = control.get_mut();
+        conn.write_all(format!("{command}\r\n").as_bytes()).await?;
+        conn.flush().await?;
+    }
+    let (code, message) = read_reply(control).await?;
+    if !expected.contains(&code) {
+        let command = match command.strip_prefix("PASS ") {
+            Some(_) => "PASS ***".into(),
+            None => command.into(),
+        };
+        return Err(FtpError::Reply {
+            command,
+            code,
+            message,
+        });
+    }
+    Ok((code, message))
+}
+
+/// The port from `229 Entering Extended Passive Mode (|||6446|)`.
+fn parse_epsv(message: &str) -> Option<u16> {
+    let start = message.find('(')?;
+    let inner = &message[start + 1..message[start..].find(')')? + start];
+    inner.trim_matches('|').parse().ok()
+}
+
+/// The port from `227 Entering Passive Mode (h1,h2,h3,h4,p1,p2)`. The address
+/// is ignored in favour of the control connection's, as it is often wrong
+/// behind NAT.
+fn parse_pasv(message: &str) -> Option<u16> {
+    let start = message.find(|c: char| c.is_ascii_digit())?;
+    let numbers: Vec<u16> = message[start..]
+        .split(|c: char| !c.is_ascii_digit())
+        .take(6)
+        .map(|n| n.parse().ok())
+        .collect::<Option<_>>()?;
+    let [.., p1, p2] = numbers[..] else {
+        return None;
+    };
+    (numbers.len() == 6 && p1 < 256 && p2 < 256).then_some((p1 << 8) | p2)
+}
+
+/// Parses `MDTM` replies (`YYYYMMDDHHMMSS[.sss]`, UTC) into an HTTP date, so
+/// that it can be compared like `Last-Modified`.
+fn parse_mdtm(message: &str) -> Option<String> {
+    let digits = message.trim().get(..14)?;
+    let field = |range: std::ops::Range<usize>| digits.get(range)?.parse::<u16>().ok();
+    let date = Date::from_calendar_date(
+        field(0..4)? as i32,
+        Month::try_from(field(4..6)? as u8).ok()?,
+        field(6..8)? as u8,
+    )
+    .ok()?;
+    let time = Time::from_hms(
+        field(8..10)? as u8,
+        field(10..12)? as u8,
+        field(12..14)? as u8,
+    )
+    .ok()?;
+    let t = PrimitiveDateTime::new(date, time).assume_utc();
+    Some(httpdate::fmt_http_date(t.into()))
+}
+
+/// The path relative to the login directory, as in RFC 1738.
+fn remote_path(url: &Url) -> String {
+    let path = url.path().strip_prefix('/').unwrap_or(url.path());
+    percent_decode_str(path).decode_utf8_lossy().into_owned()
+}
+
+struct Session {
+    control: BufReader<Conn>,
+    peer: IpAddr,
+    local: IpAddr,
+    tls: Option<(TlsConnector, ServerName<'static>)>,
+    passive: bool,
+}
+
+impl Session {
+    /// `ftps://` negotiates `AUTH TLS` on the plain connection, except on
+    /// port 990 where TLS starts right away. The host is resolved with the
+    /// same `resolve`, DoH and IP preference settings as HTTP.
+    async fn connect(url: &Url, options: &FtpOptions) -> Result<Self, FtpError> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| FtpError::InvalidUrl(url.clone()))?
+            .trim_matches(['[', ']']);
+        let port = url.port().unwrap_or(21);
+        let implicit = url.scheme() == "ftps" && port == 990;
+        let tcp = TcpStream::connect(&*options.dns.lookup(host, port).await?).await?;
+        let (peer, local) = (tcp.peer_addr()?.ip(), tcp.local_addr()?.ip());
+        let tls = match &options.tls {
+            Some(config) => Some((
+                TlsConnector::from(config.clone()),
+                ServerName::try_from(host.to_string())
+                    .map_err(|_| FtpError::InvalidUrl(url.clone()))?,
+            )),
+            None => None,
+        };
+        let conn = match &tls {
+            Some((connector, name)) if implicit => {
+                Conn::Tls(Box::new(connector.connect(name.clone(), tcp).await?))
+            }
+            _ => Conn::Plain(tcp),
+        };
+        let mut control = BufReader::new(conn);
+        command(&mut control, "", &[220]).await?;
+        if let Some((connector, name)) = &tls {
+            if !implicit {
+                command(&mut control, "AUTH TLS", &[234]).await?;
+                let Conn::Plain(tcp) = control.into_inner() else {
+                    unreachable!()
+                };
+                let stream = connector.connect(name.clone(), tcp).await?;
+                control = BufReader::new(Conn::Tls(Box::new(stream)));
+            }
+            command(&mut control, "PBSZ 0", &[200]).await?;
+            command(&mut control, "PROT P", &[200]).await?;
+        }
+        let user = format!("USER {}", options.user);
+        if command(&mut control, &user, &[230, 331]).await?.0 == 331 {
+            let passwd = format!("PASS {}", options.passwd.expose());
+            command(&mut control, &passwd, &[202, 230]).await?;
+        }
+        command(&mut control, "TYPE I", &[200]).await?;
+        Ok(Self {
+            control,
+            peer,
+            local,
+            tls,
+            passive: options.passive,
+        })
+    }
+
+    async fn command(&mut self, cmd: &str, expected: &[u16]) -> Result<(u16, String), FtpError> {
+        command(&mut self.control, cmd, expected).await
+    }
+
+    /// Starts downloading `path` from `offset` and returns the data connection.
+    async fn retrieve(&mut self, path: &str, offset: u64) -> Result<Conn, FtpError> {
+        let retr = format!("RETR {path}");
+        let tcp = if self.passive {
+            let port = match self.command("EPSV", &[229]).await {
+                Ok((_, message)) => parse_epsv(&message),
+                Err(FtpError::Reply { .. }) => parse_pasv(&self.command("PASV", &[227]).await?.1),
+                Err(e) => return Err(e),
+            }
+            .ok_or_else(|| malformed("PASV"))?;
+            let tcp = TcpStream::connect(SocketAddr::new(self.peer, port)).await?;
+            if offset > 0 {
+                self.command(&format!("REST {offset}"), &[350]).await?;
+            }
+            self.command(&retr, &[125, 150]).await?;
+            tcp
+        } else {
+            let listener = TcpListener::bind(SocketAddr::new(self.local, 0)).await?;
+            let port = listener.local_addr()?.port();
+            let cmd = match self.local {
+                IpAddr::V4(ip) => {
+                    let [a, b, c, d] = ip.octets();
+                    format!("PORT {a},{b},{c},{d},{},{}", port >> 8, port & 0xff)
+                }
+                IpAddr::V6(ip) => format!("EPRT |2|{ip}|{port}|"),
+            };
+            self.command(&cmd, &[200]).await?;
+            if offset > 0 {
+                self.command(&format!("REST {offset}"), &[350]).await?;
+            }
+            self.command(&retr, &[125, 150]).await?;
+            tokio::time::timeout(Duration::from_secs(30), listener.accept())
+                .await
+                .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??
+                .0
+        };
+        Ok(match &self.tls {
+            Some((connector, name)) => {
+                Conn::Tls(Box::new(connector.connect(name.clone(), tcp).await?))
+            }
+            None => Conn::Plain(tcp),
+        })
+    }
+}
+
+/// Reads the size with `SIZE`, the modification time with `MDTM`, and checks
+/// that `REST` is accepted so that the file can be split.
+pub async fn prefetch(url: &Url, options: &FtpOptions) -> Result<UrlInfo, FtpError> {
+    let mut session = Session::connect(url, options).await?;
+    let path = remote_path(url);
+    let size = match session.command(&format!("SIZE {path}"), &[213]).await {
+        Ok((_, message)) => Some(message.trim().parse().map_err(|_| malformed(&message))?),
+        Err(FtpError::Reply { .. }) => None,
+        Err(e) => return Err(e),
+    };
+    let last_modified = match session.command(&format!("MDTM {path}"), &[213]).await {
+        Ok((_, message)) => parse_mdtm(&message),
+        Err(FtpError::Reply { .. }) => None,
+        Err(e) => return Err(e),
+    };
+    let resumable = match session.command("REST 0", &[350]).await {
+        Ok(_) => true,
+        Err(FtpError::Reply { .. }) => false,
+        Err(e) => return Err(e),
+    };
+    let _ = session.command("QUIT", &[221]).await;
+    let name = path.rsplit('/').next().unwrap_or_default();
+    Ok(UrlInfo {
+        name: match name {
+            "" => url.host_str().unwrap_or_default().to_string(),
+            name => name.to_string(),
+        },
+        size: size.unwrap_or(0),
+        fast_download: resumable && size.is_some(),
+        file_id: FileId {
+            etag: None,
+            last_modified: last_modified.map(Into::into),
+        },
+    })
+}
+
+/// Every pull logs in on a connection of its own, since an FTP session can
+/// only run one transfer at a time.
+#[derive(Debug, Clone)]
+pub struct FtpPuller {
+    url: Arc<Url>,
+    options: Arc<FtpOptions>,
+}
+
+impl FtpPuller {
+    pub fn new(url: Url, options: Arc<FtpOptions>) -> Self {
+        Self {
+            url: Arc::new(url),
+            options,
+        }
+    }
+
+    /// Streams `len` bytes from `offset`, or everything after it with `None`.
+    pub async fn pull(
+        &self,
+        offset: u64,
+        len: Option<u64>,
+    ) -> Result<impl Stream<Item = Result<Bytes, FtpError>> + Send + Unpin + use<>, FtpError> {
+        let mut session = Session::connect(&self.url, &self.options).await?;
+        let data = session.retrieve(&remote_path(&self.url), offset).await?;
+        // The control connection has to stay open until the transfer is done
+        let stream = stream::try_unfold(
+            (session, data, len),
+            |(session, mut data, remaining)| async move {
+                let chunk = remaining.map_or(64 * 1024, |r| r.min(64 * 1024) as usize);
+                if chunk == 0 {
+                    return Ok(None);
+                }
+                let mut buf = vec![0; chunk];
+                let n = data.read(&mut buf).await?;
+                if n == 0 {
+                    return match remaining {
+                        Some(_) => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                        None => Ok(None),
+                    };
+                }
+                buf.truncate(n);
+                let remaining = remaining.map(|r| r - n as u64);
+                Ok(Some((Bytes::from(buf), (session, data, remaining))))
+            },
+        );
+        Ok(Box::pin(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{DnsResolver, IpPreference};
+    use futures_util::TryStreamExt;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    /// Serves `data` to one client in passive mode, returning the commands
+    /// it received.
+    async fn serve(listener: TcpListener, data: &[u8]) -> Vec<String> {
+        let (control, _) = listener.accept().await.unwrap();
+        let (read, mut write) = control.into_split();
+        let mut read = BufReader::new(read);
+        write
+            .write_all(b"220-Welcome\r\n220-to the test server\r\n220 Ready\r\n")
+            .await
+            .unwrap();
+        let (mut commands, mut data_listener, mut offset) = (Vec::new(), None, 0);
+        let mut line = String::new();
+        while read.read_line(&mut line).await.unwrap() > 0 {
+            let cmd = line.trim_end().to_string();
+            line.clear();
+            let reply = match cmd.split(' ').next().unwrap() {
+                "USER" => "331 Password required".into(),
+                "PASS" => "230 Logged in".into(),
+                "TYPE" => "200 Binary".into(),
+                "EPSV" => {
+                    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                    let port = listener.local_addr().unwrap().port();
+                    data_listener = Some(listener);
+                    format!("229 Entering Extended Passive Mode (|||{port}|)")
+                }
+                "REST" => {
+                    offset = cmd["REST ".len()..].parse().unwrap();
+                    "350 Restarting".into()
+                }
+                "RETR" => {
+                    write.write_all(b"150 Opening\r\n").await.unwrap();
+                    let (mut conn, _) = data_listener.take().unwrap().accept().await.unwrap();
+                    conn.write_all(&data[offset..]).await.unwrap();
+                    "226 Transfer complete".into()
+                }
+                _ => "502 Not implemented".into(),
+            };
+            commands.push(cmd);
+            // The client hangs up without reading the reply to `RETR`
+            if write
+                .write_all(format!("{reply}\r\n").as_bytes())
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+        commands
+    }
+
+    #[test]
+    fn test_read_multiline_reply() {
+        runtime().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let client = TcpStream::connect(addr).await.unwrap();
+            let (mut server, _) = listener.accept().await.unwrap();
+            server
+                .write_all(b"211-Features:\r\n MDTM\r\n SIZE\r\n211 End\r\n200 Next\r\n")
+                .await
+                .unwrap();
+            let mut control = BufReader::new(Conn::Plain(client));
+            let (code, message) = read_reply(&mut control).await.unwrap();
+            assert_eq!(code, 211);
+            assert_eq!(message, "Features:\n MDTM\n SIZE\n211 End");
+            assert_eq!(read_reply(&mut control).await.unwrap().0, 200);
+        });
+    }
+
+    #[test]
+    fn test_pull_from_offset() {
+        runtime().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let server = tokio::spawn(async move { serve(listener, b"hello world").await });
+            let url = Url::parse(&format!("ftp://127.0.0.1:{port}/dir/file.txt")).unwrap();
+            let options = FtpOptions {
+                user: Arc::from("alice"),
+                passwd: Secret::from("secret"),
+                passive: true,
+                tls: None,
+                dns: DnsOptions {
+                    resolve: Arc::from([]),
+                    ip_preference: IpPreference::Auto,
+                    resolver: DnsResolver::system(),
+                },
+            };
+            let puller = FtpPuller::new(url, Arc::new(options));
+            let chunks: Vec<Bytes> = puller
+                .pull(3, Some(4))
+                .await
+                .unwrap()
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(chunks.concat(), b"lo w");
+            assert_eq!(
+                server.await.unwrap(),
+                [
+                    "USER alice",
+                    "PASS secret",
+                    "TYPE I",
+                    "EPSV",
+                    "REST 3",
+                    "RETR dir/file.txt"
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_parse_replies() {
+        assert_eq!(
+            parse_epsv("Entering Extended Passive Mode (|||6446|)"),
+            Some(6446)
+        );
+        assert_eq!(
+            parse_pasv("Entering Passive Mode (192,168,1,2,19,137)."),
+            Some(19 * 256 + 137)
+        );
+        assert_eq!(parse_pasv("Entering Passive Mode (1,2,3)"), None);
+        assert_eq!(
+            parse_mdtm("20240601030405.123").as_deref(),
+            Some("Sat, 01 Jun 2024 03:04:05 GMT")
+        );
+        assert_eq!(parse_mdtm("2024"), None);
+    }
+
+    #[test]
+    fn test_reject_injection() {
+        let url = Url::parse("ftp://h/a%0D%0ADELE%20b").unwrap();
+        let retr = format!("RETR {}", remote_path(&url));
+        assert!(matches!(
+            check_command(&retr),
+            Err(FtpError::UnsafeArgument)
+        ));
+        assert!(matches!(
+            check_command("USER a\0b"),
+            Err(FtpError::UnsafeArgument)
+        ));
+        assert!(check_command("RETR dir/file name.txt").is_ok());
+    }
+}
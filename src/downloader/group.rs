@@ -0,0 +1,77 @@
+use crate::entry::DownloadEntry;
+use aria2_gid::Gid;
+use std::time::Instant;
+
+/// Several tasks that are tracked and controlled together.
+#[derive(Debug, Clone)]
+pub struct TaskGroup {
+    pub gid: Gid,
+    pub name: String,
+    pub members: Vec<Gid>,
+    /// Higher runs first; ungrouped tasks count as `0`
+    pub priority: i32,
+    pub(crate) completed: bool,
+    sample: Option<(Instant, u64)>,
+    speed: u64,
+}
+
+impl TaskGroup {
+    pub fn new(gid: Gid, name: String, members: Vec<Gid>) -> Self {
+        Self {
+            gid,
+            name,
+            members,
+            priority: 0,
+            completed: false,
+            sample: None,
+            speed: 0,
+        }
+    }
+
+    pub fn progress<'a>(&mut self, list: impl Iterator<Item = &'a DownloadEntry>) -> GroupProgress {
+        let mut progress = GroupProgress::default();
+        for entry in list.filter(|entry| self.members.contains(&entry.gid)) {
+            progress.add(entry);
+        }
+        let now = Instant::now();
+        match self.sample {
+            Some((at, downloaded)) if now.duration_since(at).as_secs_f64() >= 1.0 => {
+                let elapsed = now.duration_since(at).as_secs_f64();
+                self.speed =
+                    (progress.downloaded.saturating_sub(downloaded) as f64 / elapsed) as u64;
+                self.sample = Some((now, progress.downloaded));
+            }
+            Some(_) => {}
+            None => self.sample = Some((now, progress.downloaded)),
+        }
+        progress.speed = self.speed;
+        progress
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GroupProgress {
+    /// Sum of the sizes known so far
+    pub total: u64,
+    pub downloaded: u64,
+    /// Bytes per second, averaged since the previous sample
+    pub speed: u64,
+    pub tasks: usize,
+    pub completed: usize,
+}
+
+impl GroupProgress {
+    pub fn add(&mut self, entry: &DownloadEntry) {
+        let (total, downloaded) = entry.progress();
+        self.total += total;
+        self.downloaded += downloaded;
+        self.tasks += 1;
+        if entry.is_complete() {
+            self.completed += 1;
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.tasks > 0 && self.completed == self.tasks
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{
+    auth::{redact_text, redact_url},
+    config::DownloadConfig,
+};
+use reqwest::{Client, header::CONTENT_TYPE};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::process::Command;
+use url::Url;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Hook {
+    /// Run with `gid`, `event` and `path` as arguments, mirrored in
+    /// `FAST_DOWN_*` environment variables
+    Command(Arc<Path>),
+    /// POST the payload as JSON
+    Webhook(Url),
+}
+
+impl Hook {
+    /// `http(s)://` URLs become webhooks, anything else a command.
+    pub fn parse(value: &str) -> Self {
+        match Url::parse(value) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => Self::Webhook(url),
+            _ => Self::Command(Path::new(value).into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    Start,
+    Complete,
+    Error,
+    Pause,
+}
+
+impl HookEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Start => "start",
+            Self::Complete => "complete",
+            Self::Error => "error",
+            Self::Pause => "pause",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HookPayload {
+    pub gid: String,
+    pub event: HookEvent,
+    pub path: Option<PathBuf>,
+    pub error: Option<String>,
+}
+
+impl HookPayload {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "gid": self.gid,
+            "event": self.event.as_str(),
+            "path": self.path.as_ref().map(|path| path.to_string_lossy()),
+            "error": self.error,
+        })
+    }
+}
+
+/// Starts the hooks configured for `payload.event` in the background.
+pub fn fire(config: &DownloadConfig, payload: HookPayload) {
+    let hooks = match payload.event {
+        HookEvent::Start => config.on_start.clone(),
+        HookEvent::Complete => config.on_complete.clone(),
+        HookEvent::Error => config.on_error.clone(),
+        HookEvent::Pause => config.on_pause.clone(),
+    }
+    .unwrap();
+    let timeout = config.hook_timeout.unwrap();
+    for hook in hooks.iter().cloned() {
+        let payload = payload.clone();
+        tokio::spawn(async move { run_hook(&hook, timeout, &payload).await });
+    }
+}
+
+async fn run_hook(hook: &Hook, timeout: Duration, payload: &HookPayload) {
+    let event = payload.event.as_str();
+    match hook {
+        Hook::Command(program) => {
+            let path = payload.path.clone().unwrap_or_default();
+            let output = Command::new(&**program)
+                .arg(&payload.gid)
+                .arg(event)
+                .arg(&path)
+                .env("FAST_DOWN_GID", &payload.gid)
+                .env("FAST_DOWN_EVENT", event)
+                .env("FAST_DOWN_PATH", &path)
+                .env(
+                    "FAST_DOWN_ERROR",
+                    payload.error.as_deref().unwrap_or_default(),
+                )
+                .kill_on_drop(true)
+                .output();
+            match tokio::time::timeout(timeout, output).await {
+                Ok(Ok(output)) => log::info!(
+                    "hooks.run_hook(): {program:?} {event} {}: {}, stdout: {:?}, stderr: {:?}",
+                    payload.gid,
+                    output.status,
+                    String::from_utf8_lossy(&output.stdout),
+                    String::from_utf8_lossy(&output.stderr),
+                ),
+                Ok(Err(e)) => log::error!("hooks.run_hook(): Cannot run {program:?}: {e:?}"),
+                Err(_) => log::error!("hooks.run_hook(): {program:?} timed out after {timeout:?}"),
+            }
+        }
+        Hook::Webhook(url) => {
+            let shown = redact_url(url);
+            let res = Client::new()
+                .post(url.clone())
+                .header(CONTENT_TYPE, "application/json")
+                .body(payload.to_json().to_string())
+                .timeout(timeout)
+                .send()
+                .await;
+            match res {
+                Ok(resp) => {
+                    let status = resp.status();
+                    let body = resp.text().await.unwrap_or_default();
+                    log::info!(
+                        "hooks.run_hook(): POST {shown} {event} {}: {status}, body: {body:?}",
+                        payload.gid
+                    );
+                }
+                Err(e) => {
+                    let e = redact_text(&format!("{e:?}"));
+                    log::error!("hooks.run_hook(): POST {shown} failed: {e}")
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hook() {
+        assert_eq!(
+            Hook::parse("https://example.com/hook"),
+            Hook::Webhook(Url::parse("https://example.com/hook").unwrap())
+        );
+        assert_eq!(
+            Hook::parse("/usr/local/bin/notify"),
+            Hook::Command(Path::new("/usr/local/bin/notify").into())
+        );
+    }
+}
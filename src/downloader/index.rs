@@ -0,0 +1,343 @@
+use crate::{
+    auth::redact_url,
+    config::DownloadConfig,
+    puller::{ClientOptions, build_client},
+    tls::TlsError,
+    validate::FieldError,
+};
+use percent_encoding::percent_decode_str;
+use std::{collections::VecDeque, path::PathBuf};
+use url::Url;
+
+#[derive(Debug)]
+pub enum IndexError {
+    Tls(TlsError),
+    Http(reqwest::Error),
+    Config(Vec<FieldError>),
+}
+
+/// A file name filter; `re:` selects a regex, anything else is a glob.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Glob(glob::Pattern),
+    Regex(regex::Regex),
+}
+
+impl Pattern {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.strip_prefix("re:") {
+            Some(re) => regex::Regex::new(re).ok().map(Self::Regex),
+            None => glob::Pattern::new(value).ok().map(Self::Glob),
+        }
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        match self {
+            Self::Glob(glob) => glob.matches(path),
+            Self::Regex(re) => re.is_match(path),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexOptions {
+    /// The index page; should end with `/`
+    pub url: Url,
+    /// `0` only takes the files listed on `url` itself
+    pub max_depth: usize,
+    /// Matched against the path relative to `url`; empty accepts everything
+    pub include: Vec<Pattern>,
+    pub exclude: Vec<Pattern>,
+    /// Directory relative to `save_dir` that mirrors `url`
+    pub subdir: Option<PathBuf>,
+    pub immediate_download: bool,
+    pub config: DownloadConfig,
+}
+
+impl IndexOptions {
+    fn accepts(&self, path: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(path)))
+            && !self.exclude.iter().any(|p| p.matches(path))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexFile {
+    pub url: Url,
+    /// Remote directories between the index root and the file
+    pub dir: PathBuf,
+    pub name: String,
+}
+
+/// `url` as a directory, i.e. with a trailing `/` on its path.
+fn as_dir(url: &Url) -> Url {
+    let mut dir = url.clone();
+    if !dir.path().ends_with('/') {
+        dir.set_path(&format!("{}/", url.path()));
+    }
+    dir
+}
+
+/// The values of all `href` attributes, matched case-insensitively and
+/// quoted or not.
+fn hrefs(html: &str) -> Vec<&str> {
+    let lower = html.to_ascii_lowercase();
+    let mut values = Vec::new();
+    let mut pos = 0;
+    while let Some(found) = lower[pos..].find("href") {
+        let name = pos + found;
+        pos = name + 4;
+        if !lower[..name].ends_with(|c: char| c.is_ascii_whitespace()) {
+            continue;
+        }
+        let rest = html[pos..].trim_start();
+        let Some(rest) = rest.strip_prefix('=') else {
+            continue;
+        };
+        let rest = rest.trim_start();
+        let (quote, value) = match rest.chars().next() {
+            Some(q @ ('"' | '\'')) => (Some(q), &rest[1..]),
+            _ => (None, rest),
+        };
+        let end = match quote {
+            Some(q) => value.find(q),
+            None => value.find(|c: char| c.is_ascii_whitespace() || c == '>'),
+        }
+        .unwrap_or(value.len());
+        values.push(&value[..end]);
+        pos = html.len() - value.len() + end;
+    }
+    values
+}
+
+/// Collects the `href` targets of an autoindex page that point below `page`,
+/// skipping sort links (`?C=N;O=D`), fragments and the parent directory.
+pub fn parse_links(page: &Url, html: &str) -> Vec<Url> {
+    let page = as_dir(page);
+    let mut links = Vec::new();
+    for href in hrefs(html) {
+        let Ok(mut url) = page.join(&href.replace("&amp;", "&")) else {
+            continue;
+        };
+        url.set_fragment(None);
+        if url.query().is_some()
+            || url == page
+            || url.origin() != page.origin()
+            || !url.path().starts_with(page.path())
+            || links.contains(&url)
+        {
+            continue;
+        }
+        links.push(url);
+    }
+    links
+}
+
+fn decode_segments(root: &Url, url: &Url) -> Option<Vec<String>> {
+    let rel = url.path().strip_prefix(as_dir(root).path())?;
+    rel.split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| {
+            let name = percent_decode_str(segment).decode_utf8().ok()?;
+            let safe = sanitize_filename::sanitize_with_options(
+                &name,
+                sanitize_filename::Options {
+                    windows: cfg!(windows),
+                    truncate: true,
+                    replacement: "_",
+                },
+            );
+            (!safe.is_empty() && safe != "." && safe != "..").then_some(safe)
+        })
+        .collect()
+}
+
+/// Walks the index breadth-first and returns the accepted files.
+pub async fn crawl(
+    options: &IndexOptions,
+    config: &DownloadConfig,
+) -> Result<Vec<IndexFile>, IndexError> {
+    let client_options = ClientOptions::new(config, &options.url).map_err(IndexError::Tls)?;
+    let client = build_client(&client_options).map_err(IndexError::Http)?;
+    let mut files = Vec::new();
+    let mut visited = vec![options.url.clone()];
+    let mut queue = VecDeque::from([(options.url.clone(), 0)]);
+    while let Some((page, depth)) = queue.pop_front() {
+        log::debug!(
+            "index.crawl(): Fetching {} at depth {depth}",
+            redact_url(&page)
+        );
+        let html = client
+            .get(page.clone())
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map_err(IndexError::Http)?
+            .text()
+            .await
+            .map_err(IndexError::Http)?;
+        for link in parse_links(&page, &html) {
+            let Some(segments) = decode_segments(&options.url, &link) else {
+                log::warn!("index.crawl(): Skipping unsafe link {}", redact_url(&link));
+                continue;
+            };
+            if link.path().ends_with('/') {
+                if depth < options.max_depth && !visited.contains(&link) {
+                    visited.push(link.clone());
+                    queue.push_back((link, depth + 1));
+                }
+            } else if let Some((name, dir)) = segments.split_last()
+                && options.accepts(&segments.join("/"))
+            {
+                files.push(IndexFile {
+                    url: link,
+                    dir: dir.iter().collect(),
+                    name: name.clone(),
+                });
+            }
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    #[test]
+    fn test_parse_links() {
+        let page = Url::parse("https://example.com/pub/").unwrap();
+        let html = r#"<a href="?C=N;O=D">Name</a>
+            <a href="../">Parent</a>
+            <a href="iso/">iso/</a>
+            <a href='a%20b.txt'>a b.txt</a>
+            <a href=https://other.example.com/x>x</a>
+            <a href="/pub/iso/">dup</a>"#;
+        let links: Vec<_> = parse_links(&page, html)
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            links,
+            [
+                "https://example.com/pub/iso/",
+                "https://example.com/pub/a%20b.txt"
+            ]
+        );
+        let segments = decode_segments(&page, &Url::parse(&links[1]).unwrap());
+        assert_eq!(segments, Some(vec!["a b.txt".to_string()]));
+
+        let page = Url::parse("https://example.com/pub").unwrap();
+        let html = r#"<A HREF=x.iso>x</A> <a data-href="y.iso" Href = 'z.iso'>z</a>
+            <a href="/public/w.iso">w</a>"#;
+        let links: Vec<_> = parse_links(&page, html)
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            links,
+            [
+                "https://example.com/pub/x.iso",
+                "https://example.com/pub/z.iso"
+            ]
+        );
+    }
+
+    /// Serves a two-level autoindex below `/pub/`.
+    async fn serve_index(listener: TcpListener) {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buf = vec![0; 4096];
+                let n = stream.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]);
+                let path = request.split(' ').nth(1).unwrap_or_default();
+                let body = match path {
+                    "/pub/" => {
+                        r#"<a href="../">../</a> <A HREF=sub/>sub/</A>
+                        <a href="a.iso">a.iso</a> <a href="a.txt">a.txt</a>
+                        <a href="/public/b.iso">b.iso</a>"#
+                    }
+                    "/pub/sub/" => r#"<a href="../">../</a> <a href='c.iso'>c.iso</a>"#,
+                    _ => "",
+                };
+                let status = if body.is_empty() {
+                    "404 Not Found"
+                } else {
+                    "200 OK"
+                };
+                let _ = stream
+                    .write_all(
+                        format!(
+                            "HTTP/1.1 {status}\r\nContent-Length: {}\r\n\
+                             Connection: close\r\n\r\n{body}",
+                            body.len()
+                        )
+                        .as_bytes(),
+                    )
+                    .await;
+            });
+        }
+    }
+
+    #[test]
+    fn test_crawl() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let root =
+                Url::parse(&format!("http://{}/pub/", listener.local_addr().unwrap())).unwrap();
+            tokio::spawn(serve_index(listener));
+            let options = IndexOptions {
+                url: root.clone(),
+                max_depth: 1,
+                include: vec![Pattern::parse("*.iso").unwrap()],
+                exclude: Vec::new(),
+                subdir: None,
+                immediate_download: false,
+                config: DownloadConfig::empty(),
+            };
+            let files = crawl(&options, &DownloadConfig::default()).await.unwrap();
+            assert_eq!(
+                files,
+                [
+                    IndexFile {
+                        url: root.join("a.iso").unwrap(),
+                        dir: PathBuf::new(),
+                        name: "a.iso".into(),
+                    },
+                    IndexFile {
+                        url: root.join("sub/c.iso").unwrap(),
+                        dir: PathBuf::from("sub"),
+                        name: "c.iso".into(),
+                    },
+                ]
+            );
+
+            let shallow = IndexOptions {
+                max_depth: 0,
+                ..options
+            };
+            let files = crawl(&shallow, &DownloadConfig::default()).await.unwrap();
+            assert_eq!(files.len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_pattern() {
+        assert!(Pattern::parse("*.iso").unwrap().matches("x/debian.iso"));
+        assert!(!Pattern::parse("*.iso").unwrap().matches("x/debian.img"));
+        assert!(
+            Pattern::parse(r"re:^x/.*\.img$")
+                .unwrap()
+                .matches("x/a.img")
+        );
+        assert!(Pattern::parse("re:(").is_none());
+    }
+}
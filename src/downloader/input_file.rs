@@ -0,0 +1,333 @@
+use crate::{
+    allocation::FileAllocation,
+    checksum::{Checksum, ChecksumError},
+    config::DownloadConfig,
+    entry::{AddOptions, DependencyPolicy},
+    file_conflict::FileConflict,
+    hooks::Hook,
+    safe_path::check_relative,
+    validate::FieldError,
+};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::{
+    num::{NonZeroU64, NonZeroUsize},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+use url::Url;
+
+#[derive(Debug)]
+pub enum InputFileError {
+    InvalidUrl {
+        line: usize,
+        error: url::ParseError,
+    },
+    OptionWithoutUri {
+        line: usize,
+    },
+    InvalidOption {
+        line: usize,
+        name: String,
+        value: String,
+    },
+    UnsupportedOption {
+        line: usize,
+        name: String,
+    },
+    /// An option that [`InputSource::Remote`] input may not set
+    ForbiddenOption {
+        line: usize,
+        name: String,
+    },
+    Config(Vec<FieldError>),
+}
+
+/// Where an input file comes from, which decides the options it may set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputSource {
+    /// Written by the local user, e.g. passed on the command line
+    Local,
+    /// Received over the network; it cannot run hooks or read and write
+    /// arbitrary local paths
+    Remote,
+}
+
+/// Options that run commands, name local files, or overwrite existing ones.
+const LOCAL_ONLY: &[&str] = &[
+    "dir",
+    "allow-overwrite",
+    "netrc-path",
+    "load-cookies",
+    "save-cookies",
+    "ca-certificate",
+    "certificate",
+    "private-key",
+    "on-download-start",
+    "on-download-complete",
+    "on-download-error",
+    "on-download-pause",
+];
+
+/// Parses sizes such as `1048576`, `512K` or `20M`.
+pub fn parse_size(value: &str) -> Option<u64> {
+    let (number, unit) = match value.char_indices().last()? {
+        (i, 'k' | 'K') => (&value[..i], 1024),
+        (i, 'm' | 'M') => (&value[..i], 1024 * 1024),
+        (i, 'g' | 'G') => (&value[..i], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    number.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+fn push_hook(hooks: &mut Option<Arc<[Hook]>>, value: &str) {
+    let mut list = hooks.as_deref().unwrap_or_default().to_vec();
+    list.push(Hook::parse(value));
+    *hooks = Some(list.into());
+}
+
+enum OptionError {
+    Invalid,
+    Unsupported,
+}
+
+fn apply_option(options: &mut AddOptions, name: &str, value: &str) -> Result<(), OptionError> {
+    use OptionError::Invalid;
+    let config = &mut options.config;
+    let path = || Some(Arc::<Path>::from(Path::new(value)));
+    let text = || Some(Arc::<str>::from(value));
+    match name {
+        "dir" => config.save_dir = path(),
+        "out" => {
+            check_relative(Path::new(value)).map_err(|_| Invalid)?;
+            options.out = Some(value.into());
+        }
+        "allow-overwrite" if parse_bool(value).ok_or(Invalid)? => {
+            config.file_conflict = Some(FileConflict::Overwrite)
+        }
+        "continue" if parse_bool(value).ok_or(Invalid)? => {
+            config.file_conflict = Some(FileConflict::Resume)
+        }
+        "conditional-get" if parse_bool(value).ok_or(Invalid)? => {
+            config.file_conflict = Some(FileConflict::Update)
+        }
+        "allow-overwrite" | "continue" | "conditional-get" => {}
+        "header" => {
+            let (key, val) = value.split_once(':').ok_or(Invalid)?;
+            let key = HeaderName::try_from(key.trim()).map_err(|_| Invalid)?;
+            let val = HeaderValue::try_from(val.trim()).map_err(|_| Invalid)?;
+            let mut headers = config
+                .headers
+                .as_deref()
+                .cloned()
+                .unwrap_or_else(HeaderMap::new);
+            headers.append(key, val);
+            config.headers = Some(Arc::new(headers));
+        }
+        "file-allocation" => {
+            config.file_allocation = Some(FileAllocation::parse(value).ok_or(Invalid)?)
+        }
+        "checksum" => match Checksum::parse(value) {
+            Ok(checksum) => options.checksum = Some(checksum),
+            Err(ChecksumError::UnsupportedAlgo(algo)) => {
+                log::warn!("input_file.apply_option(): Ignoring {algo} checksum, not supported")
+            }
+            Err(ChecksumError::Invalid) => return Err(Invalid),
+        },
+        "split" => config.threads = Some(value.parse::<NonZeroUsize>().map_err(|_| Invalid)?),
+        "min-split-size" => {
+            config.min_chunk_size =
+                Some(parse_size(value).and_then(NonZeroU64::new).ok_or(Invalid)?)
+        }
+        "retry-wait" => {
+            config.retry_gap = Some(Duration::from_secs(value.parse().map_err(|_| Invalid)?))
+        }
+        "all-proxy" => config.all_proxy = text(),
+        "http-proxy" => config.http_proxy = text(),
+        "https-proxy" => config.https_proxy = text(),
+        "no-proxy" => config.no_proxy = text(),
+        "all-proxy-user" => config.proxy_user = text(),
+        "all-proxy-passwd" => config.proxy_passwd = Some(value.into()),
+        "http-user" => config.http_user = text(),
+        "http-passwd" => config.http_passwd = Some(value.into()),
+        "ftp-user" => config.ftp_user = text(),
+        "ftp-passwd" => config.ftp_passwd = Some(value.into()),
+        "ftp-pasv" => config.ftp_passive = Some(parse_bool(value).ok_or(Invalid)?),
+        "netrc-path" => config.netrc_path = path(),
+        "no-netrc" => config.netrc = Some(!parse_bool(value).ok_or(Invalid)?),
+        "load-cookies" => config.load_cookies = path(),
+        "save-cookies" => config.save_cookies = path(),
+        "check-certificate" => {
+            config.accept_invalid_certs = Some(!parse_bool(value).ok_or(Invalid)?)
+        }
+        "ca-certificate" => {
+            let mut ca_certs = config.ca_certs.as_deref().unwrap_or_default().to_vec();
+            ca_certs.push(Path::new(value).into());
+            config.ca_certs = Some(ca_certs.into());
+        }
+        "on-download-start" => push_hook(&mut config.on_start, value),
+        "on-download-complete" => push_hook(&mut config.on_complete, value),
+        "on-download-error" => push_hook(&mut config.on_error, value),
+        "on-download-pause" => push_hook(&mut config.on_pause, value),
+        "certificate" => config.client_cert = path(),
+        "private-key" => config.client_key = path(),
+        _ => return Err(OptionError::Unsupported),
+    }
+    Ok(())
+}
+
+/// Parses aria2's `--input-file` format: one task per line of tab-separated
+/// mirror URIs, followed by indented `name=value` options for that task.
+pub fn parse_input_file(
+    content: &str,
+    source: InputSource,
+) -> Result<Vec<AddOptions>, InputFileError> {
+    let mut tasks: Vec<AddOptions> = Vec::new();
+    for (i, raw) in content.lines().enumerate() {
+        let line = i + 1;
+        let trimmed = raw.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if raw.starts_with([' ', '\t']) {
+            let task = tasks
+                .last_mut()
+                .ok_or(InputFileError::OptionWithoutUri { line })?;
+            let (name, value) = trimmed.split_once('=').unwrap_or((trimmed, ""));
+            if source == InputSource::Remote && LOCAL_ONLY.contains(&name) {
+                return Err(InputFileError::ForbiddenOption {
+                    line,
+                    name: name.to_string(),
+                });
+            }
+            match apply_option(task, name, value) {
+                Ok(()) => {}
+                Err(OptionError::Invalid) => {
+                    return Err(InputFileError::InvalidOption {
+                        line,
+                        name: name.to_string(),
+                        value: value.to_string(),
+                    });
+                }
+                Err(OptionError::Unsupported) => {
+                    return Err(InputFileError::UnsupportedOption {
+                        line,
+                        name: name.to_string(),
+                    });
+                }
+            }
+            continue;
+        }
+        let mut uris = trimmed
+            .split('\t')
+            .filter(|uri| !uri.is_empty())
+            .map(|uri| Url::parse(uri).map_err(|error| InputFileError::InvalidUrl { line, error }));
+        let url = uris.next().unwrap()?;
+        tasks.push(AddOptions {
+            url,
+            mirrors: uris.collect::<Result<_, _>>()?,
+            immediate_download: true,
+            config: DownloadConfig::empty(),
+            checksum: None,
+            out: None,
+            subdir: None,
+            depends_on: Vec::new(),
+            on_dependency_error: DependencyPolicy::Fail,
+        });
+    }
+    Ok(tasks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_input_file() {
+        let tasks = parse_input_file(
+            "# nightly\n\
+             https://a.example.com/f.iso\thttps://b.example.com/f.iso\n\
+             \x20 dir=/tmp/iso\n\
+             \x20 header=X-Token: abc\n\
+             \x20 split=4\n\
+             \n\
+             https://c.example.com/g.zip\n\
+             \x20 out=g-latest.zip\n",
+            InputSource::Local,
+        )
+        .unwrap();
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[0].url.as_str(), "https://a.example.com/f.iso");
+        assert_eq!(tasks[0].mirrors[0].as_str(), "https://b.example.com/f.iso");
+        assert_eq!(
+            tasks[0].config.save_dir.as_deref(),
+            Some(Path::new("/tmp/iso"))
+        );
+        assert_eq!(tasks[0].config.headers.as_ref().unwrap()["x-token"], "abc");
+        assert_eq!(tasks[0].config.threads.unwrap().get(), 4);
+        assert_eq!(tasks[1].out.as_deref(), Some(Path::new("g-latest.zip")));
+        assert!(tasks[1].mirrors.is_empty());
+        assert!(tasks[1].config.save_dir.is_none());
+
+        let tasks = parse_input_file(
+            "https://a.example.com/\n  checksum=md5=d41d8cd98f00b204e9800998ecf8427e",
+            InputSource::Local,
+        )
+        .unwrap();
+        assert!(tasks[0].checksum.is_none());
+        assert!(matches!(
+            parse_input_file(
+                "https://a.example.com/\n  checksum=sha-256=",
+                InputSource::Local
+            ),
+            Err(InputFileError::InvalidOption { line: 2, .. })
+        ));
+        assert!(matches!(
+            parse_input_file("  dir=/tmp", InputSource::Local),
+            Err(InputFileError::OptionWithoutUri { line: 1 })
+        ));
+        assert!(matches!(
+            parse_input_file("https://a.example.com/\n  split=zero", InputSource::Local),
+            Err(InputFileError::InvalidOption { line: 2, .. })
+        ));
+        assert!(matches!(
+            parse_input_file(
+                "https://a.example.com/\n  out=../escape",
+                InputSource::Local
+            ),
+            Err(InputFileError::InvalidOption { line: 2, .. })
+        ));
+        assert!(matches!(
+            parse_input_file("https://a.example.com/\n  bogus=1", InputSource::Local),
+            Err(InputFileError::UnsupportedOption { line: 2, .. })
+        ));
+        for option in [
+            "dir=/etc",
+            "on-download-complete=/bin/sh",
+            "save-cookies=/tmp/c",
+        ] {
+            let content = format!("https://a.example.com/\n  {option}");
+            assert!(matches!(
+                parse_input_file(&content, InputSource::Remote),
+                Err(InputFileError::ForbiddenOption { line: 2, .. })
+            ));
+        }
+        assert!(parse_input_file("https://a.example.com/\n  split=4", InputSource::Remote).is_ok());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024"), Some(1024));
+        assert_eq!(parse_size("512K"), Some(512 * 1024));
+        assert_eq!(parse_size("20M"), Some(20 * 1024 * 1024));
+        assert_eq!(parse_size("M"), None);
+    }
+}
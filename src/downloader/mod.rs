@@ -1,21 +1,84 @@
+pub mod allocation;
+pub mod auth;
+pub mod checksum;
+pub mod conditional;
 pub mod config;
+pub mod control;
+pub mod cookie_jar;
+pub mod disk_space;
+pub mod dns;
 pub mod entry;
+pub mod extract;
+pub mod file_conflict;
+pub mod fnv;
+pub mod ftp;
+pub mod group;
+pub mod hooks;
+pub mod index;
+pub mod input_file;
 pub mod invert;
+pub mod proxy;
 pub mod puller;
+pub mod safe_path;
+pub mod schedule;
 pub mod send_err;
+pub mod tls;
 pub mod unique_path;
+pub mod validate;
 
 use crate::{
+    auth::redact_url,
     config::DownloadConfig,
-    entry::{AddOptions, DownloadEntry},
+    disk_space::DiskSpace,
+    entry::{
+        AddOptions, ChangeUrlError, DependencyPolicy, DownloadEntry, DownloadEvent, UrlChange,
+    },
+    group::{GroupProgress, TaskGroup},
+    hooks::{HookEvent, HookPayload},
+    index::{IndexError, IndexOptions, crawl},
+    input_file::{InputFileError, InputSource, parse_input_file},
+    schedule::{ScheduleSpec, ScheduledTask, load_schedules, save_schedules},
+    validate::{FieldError, validate},
 };
 use aria2_gid::Gid;
+use inherit_config::InheritAble;
+use kanal::{AsyncReceiver, AsyncSender};
 use spin::mutex::SpinMutex;
-use std::sync::Arc;
+use std::{
+    cmp::Reverse,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use time::OffsetDateTime;
+use url::Url;
+
+#[derive(Debug, Clone)]
+pub enum DownloaderEvent {
+    /// Every member of the group has finished downloading
+    GroupCompleted(Gid),
+    /// The global config changed; holds the names of the changed fields
+    ConfigChanged(Arc<[&'static str]>),
+}
+
+#[derive(Debug)]
+pub enum AddTaskError {
+    Config(Vec<FieldError>),
+    UnknownPrerequisite(Gid),
+}
 
 pub struct Downloader {
     list: Arc<SpinMutex<Vec<DownloadEntry>>>,
+    /// Removed tasks that had completed, which still satisfy the tasks
+    /// depending on them
+    completed: Arc<SpinMutex<Vec<Gid>>>,
     parallelism: Arc<SpinMutex<usize>>,
+    disk: DiskSpace,
+    groups: Arc<SpinMutex<Vec<TaskGroup>>>,
+    schedules: Arc<SpinMutex<Vec<ScheduledTask>>>,
+    schedule_file: Arc<SpinMutex<Option<PathBuf>>>,
+    tx: AsyncSender<DownloaderEvent>,
+    pub event_chain: AsyncReceiver<DownloaderEvent>,
     pub config: Arc<SpinMutex<DownloadConfig>>,
 }
 
@@ -24,9 +87,17 @@ impl Downloader {
         Self::with_capacity(config, 0)
     }
     pub fn with_capacity(config: Arc<SpinMutex<DownloadConfig>>, capacity: usize) -> Self {
+        let (tx, event_chain) = kanal::unbounded_async();
         Self {
             list: Arc::new(SpinMutex::new(Vec::with_capacity(capacity))),
+            completed: Arc::new(SpinMutex::new(Vec::new())),
             parallelism: Arc::new(SpinMutex::new(0)),
+            disk: DiskSpace::new(),
+            groups: Arc::new(SpinMutex::new(Vec::new())),
+            schedules: Arc::new(SpinMutex::new(Vec::new())),
+            schedule_file: Arc::new(SpinMutex::new(None)),
+            tx,
+            event_chain,
             config,
         }
     }
@@ -35,7 +106,9 @@ impl Downloader {
         self.run();
     }
     pub fn run(self: Arc<Self>) {
-        let list = self.list.lock();
+        let mut list = self.list.lock();
+        let completed = self.completed.lock().clone();
+        resolve_dependencies(&mut list, &completed);
         let parallelism = *self.parallelism.lock();
         let running_count: usize = list
             .iter()
@@ -47,7 +120,12 @@ impl Downloader {
                 if needed == 0 {
                     break;
                 }
-                if entry.is_running() {
+                if entry.is_running()
+                    || entry.is_paused()
+                    || entry.is_finished()
+                    || entry.error().is_some()
+                    || !prerequisites_met(&list, &completed, entry)
+                {
                     continue;
                 }
                 match entry.run() {
@@ -57,8 +135,13 @@ impl Downloader {
                         let rx = entry.inner.lock().event_chain.clone();
                         let downloader = Arc::downgrade(&self);
                         tokio::spawn(async move {
-                            while (rx.recv().await).is_ok() {}
+                            while let Ok(event) = rx.recv().await {
+                                if let DownloadEvent::Finished = event {
+                                    break;
+                                }
+                            }
                             if let Some(downloader) = downloader.upgrade() {
+                                downloader.check_groups().await;
                                 downloader.run();
                             }
                         });
@@ -83,25 +166,341 @@ impl Downloader {
             }
         }
     }
-    pub fn add_task(self: Arc<Self>, options: AddOptions) -> Result<Gid, reqwest::Error> {
-        let call_dbg = format!("downloader.add_task({options:?})");
-        log::debug!("{call_dbg}");
-        let mut list = self.list.lock();
-        let gid = loop {
+    fn new_gid(&self, list: &[DownloadEntry], call_dbg: &str) -> Gid {
+        let groups = self.groups.lock();
+        let schedules = self.schedules.lock();
+        loop {
             let temp = Gid::new();
-            if list.iter().all(|entry| entry != &temp) {
+            if list.iter().all(|entry| entry != &temp)
+                && groups.iter().all(|group| group.gid != temp)
+                && schedules.iter().all(|task| task.gid != temp)
+            {
                 break temp;
             }
             log::debug!("{call_dbg}: Gid collision, retrying");
-        };
+        }
+    }
+    fn insert(&self, list: &mut Vec<DownloadEntry>, options: AddOptions) -> Gid {
+        let call_dbg = format!("downloader.add_task({:?})", options.redacted());
+        log::debug!("{call_dbg}");
+        let gid = self.new_gid(list, &call_dbg);
         log::debug!("{call_dbg}: Assigned Gid {gid}");
-        let entry = DownloadEntry::new(gid, options, self.config.clone());
+        let entry = DownloadEntry::new(gid, options, self.config.clone(), self.disk.clone());
         log::debug!("{call_dbg}: Inserted entry {gid}: {entry:?}");
         list.push(entry);
+        gid
+    }
+    pub fn add_task(self: Arc<Self>, options: AddOptions) -> Result<Gid, AddTaskError> {
+        validate(&options.config).map_err(AddTaskError::Config)?;
+        let mut list = self.list.lock();
+        check_dependencies(&list, &self.completed.lock(), &options.depends_on)?;
+        let gid = self.insert(&mut list, options);
         drop(list);
         self.run();
         Ok(gid)
     }
+    /// Adds all tasks, or none of them if one is rejected.
+    pub fn add_tasks(self: Arc<Self>, options: Vec<AddOptions>) -> Result<Vec<Gid>, AddTaskError> {
+        for options in &options {
+            validate(&options.config).map_err(AddTaskError::Config)?;
+        }
+        let list = self.list.lock();
+        let completed = self.completed.lock();
+        for options in &options {
+            check_dependencies(&list, &completed, &options.depends_on)?;
+        }
+        drop(completed);
+        drop(list);
+        Ok(self.insert_tasks(options))
+    }
+    /// Inserts tasks whose configs are already validated and that have no
+    /// prerequisites.
+    fn insert_tasks(self: Arc<Self>, options: Vec<AddOptions>) -> Vec<Gid> {
+        let mut list = self.list.lock();
+        list.reserve(options.len());
+        let gids = options
+            .into_iter()
+            .map(|options| self.insert(&mut list, options))
+            .collect();
+        drop(list);
+        self.run();
+        gids
+    }
+    /// Adds the tasks of an aria2 input file as one group, returning the group
+    /// id and the task ids.
+    pub fn import_input_file(
+        self: Arc<Self>,
+        content: &str,
+        source: InputSource,
+    ) -> Result<(Gid, Vec<Gid>), InputFileError> {
+        let options = parse_input_file(content, source)?;
+        log::debug!("downloader.import_input_file(): {} tasks", options.len());
+        for options in &options {
+            validate(&options.config).map_err(InputFileError::Config)?;
+        }
+        let gids = self.clone().insert_tasks(options);
+        let group = self.create_group("input file".into(), gids.clone());
+        Ok((group, gids))
+    }
+    /// Crawls an autoindex page and adds every accepted file as a child task.
+    pub async fn add_index(self: Arc<Self>, options: IndexOptions) -> Result<Gid, IndexError> {
+        let call_dbg = format!("downloader.add_index({})", redact_url(&options.url));
+        validate(&options.config).map_err(IndexError::Config)?;
+        let config = options
+            .config
+            .inherit(&*self.config.lock())
+            .inherit(&DownloadConfig::default());
+        let files = crawl(&options, &config).await?;
+        log::debug!("{call_dbg}: {} files", files.len());
+        let base = options.subdir.clone().unwrap_or_default();
+        let tasks = files
+            .into_iter()
+            .map(|file| {
+                let subdir = base.join(file.dir);
+                AddOptions {
+                    url: file.url,
+                    mirrors: Vec::new(),
+                    immediate_download: options.immediate_download,
+                    config: options.config.clone(),
+                    checksum: None,
+                    out: Some(file.name.into()),
+                    subdir: (!subdir.as_os_str().is_empty()).then_some(subdir),
+                    depends_on: Vec::new(),
+                    on_dependency_error: DependencyPolicy::Fail,
+                }
+            })
+            .collect();
+        let children = self.clone().insert_tasks(tasks);
+        log::debug!("{call_dbg}: {} children", children.len());
+        Ok(self.create_group(redact_url(&options.url).to_string(), children))
+    }
+    pub fn create_group(&self, name: String, members: Vec<Gid>) -> Gid {
+        let call_dbg = format!("downloader.create_group({name:?})");
+        let gid = self.new_gid(&self.list.lock(), &call_dbg);
+        log::debug!(
+            "{call_dbg}: Assigned Gid {gid} to {} members",
+            members.len()
+        );
+        self.groups.lock().push(TaskGroup::new(gid, name, members));
+        gid
+    }
+    pub fn groups(&self) -> Vec<TaskGroup> {
+        self.groups.lock().clone()
+    }
+    pub fn group_progress(&self, gid: Gid) -> Option<GroupProgress> {
+        let list = self.list.lock();
+        let mut groups = self.groups.lock();
+        let group = groups.iter_mut().find(|group| group.gid == gid)?;
+        Some(group.progress(list.iter()))
+    }
+    fn group_members(&self, gid: Gid) -> Option<Vec<Gid>> {
+        let groups = self.groups.lock();
+        let group = groups.iter().find(|group| group.gid == gid)?;
+        Some(group.members.clone())
+    }
+    /// Aborts the members and keeps them from being scheduled until resumed.
+    pub fn pause_group(self: Arc<Self>, gid: Gid) -> bool {
+        log::debug!("downloader.pause_group({gid})");
+        let Some(members) = self.group_members(gid) else {
+            return false;
+        };
+        for entry in self.list.lock().iter() {
+            if members.contains(&entry.gid) {
+                entry.set_paused(true);
+                if entry.is_running() {
+                    entry.abort();
+                }
+            }
+        }
+        self.run();
+        true
+    }
+    pub fn resume_group(self: Arc<Self>, gid: Gid) -> bool {
+        log::debug!("downloader.resume_group({gid})");
+        let Some(members) = self.group_members(gid) else {
+            return false;
+        };
+        for entry in self.list.lock().iter() {
+            if members.contains(&entry.gid) {
+                entry.set_paused(false);
+            }
+        }
+        self.run();
+        true
+    }
+    /// Removes the group together with all of its members.
+    pub fn remove_group(self: Arc<Self>, gid: Gid) -> Option<Vec<DownloadEntry>> {
+        log::debug!("downloader.remove_group({gid})");
+        let mut groups = self.groups.lock();
+        let pos = groups.iter().position(|group| group.gid == gid)?;
+        let group = groups.remove(pos);
+        drop(groups);
+        let mut list = self.list.lock();
+        let (removed, kept): (Vec<_>, Vec<_>) = list
+            .drain(..)
+            .partition(|entry| group.members.contains(&entry.gid));
+        *list = kept;
+        self.completed.lock().extend(
+            removed
+                .iter()
+                .filter(|entry| entry.is_complete())
+                .map(|entry| entry.gid),
+        );
+        drop(list);
+        for entry in removed.iter() {
+            entry.abort();
+        }
+        self.run();
+        Some(removed)
+    }
+    /// Tasks of higher priority groups are scheduled first.
+    pub fn set_group_priority(self: Arc<Self>, gid: Gid, priority: i32) -> bool {
+        log::debug!("downloader.set_group_priority({gid}, {priority})");
+        let mut list = self.list.lock();
+        let mut groups = self.groups.lock();
+        let Some(group) = groups.iter_mut().find(|group| group.gid == gid) else {
+            return false;
+        };
+        group.priority = priority;
+        list.sort_by_key(|entry| {
+            Reverse(
+                groups
+                    .iter()
+                    .filter(|group| group.members.contains(&entry.gid))
+                    .map(|group| group.priority)
+                    .max()
+                    .unwrap_or(0),
+            )
+        });
+        drop(list);
+        drop(groups);
+        self.run();
+        true
+    }
+    async fn check_groups(&self) {
+        let completed: Vec<_> = {
+            let list = self.list.lock();
+            let mut groups = self.groups.lock();
+            groups
+                .iter_mut()
+                .filter(|group| !group.completed)
+                .filter_map(|group| {
+                    group.completed = group.progress(list.iter()).is_complete();
+                    group.completed.then_some(group.gid)
+                })
+                .collect()
+        };
+        for gid in completed {
+            log::debug!("downloader.check_groups(): Group {gid} completed");
+            self.tx
+                .send(DownloaderEvent::GroupCompleted(gid))
+                .await
+                .unwrap();
+        }
+    }
+    /// Loads scheduled tasks from `path`, which is rewritten from now on
+    /// whenever schedules are added or cancelled.
+    pub async fn load_schedules(&self, path: impl AsRef<Path>) -> std::io::Result<usize> {
+        let path = path.as_ref();
+        let specs = load_schedules(path).await?;
+        log::debug!(
+            "downloader.load_schedules({path:?}): {} schedules",
+            specs.len()
+        );
+        self.schedule_file.lock().replace(path.into());
+        let count = specs.len();
+        for spec in specs {
+            self.insert_schedule(spec);
+        }
+        Ok(count)
+    }
+    fn insert_schedule(&self, spec: ScheduleSpec) -> Gid {
+        let call_dbg = format!("downloader.add_schedule({spec:?})");
+        let gid = self.new_gid(&self.list.lock(), &call_dbg);
+        let next_run = spec.schedule.next_after(OffsetDateTime::now_utc());
+        log::debug!("{call_dbg}: Assigned Gid {gid}, next run at {next_run:?}");
+        self.schedules.lock().push(ScheduledTask {
+            gid,
+            spec,
+            next_run,
+            last_task: None,
+        });
+        gid
+    }
+    async fn save_schedules(&self) {
+        let Some(path) = self.schedule_file.lock().clone() else {
+            return;
+        };
+        let specs: Vec<_> = self
+            .schedules
+            .lock()
+            .iter()
+            .map(|task| task.spec.clone())
+            .collect();
+        if let Err(e) = save_schedules(&path, &specs).await {
+            log::error!("downloader.save_schedules(): {path:?}: {e:?}");
+        }
+    }
+    pub async fn add_schedule(&self, spec: ScheduleSpec) -> Gid {
+        let gid = self.insert_schedule(spec);
+        self.save_schedules().await;
+        gid
+    }
+    pub fn schedules(&self) -> Vec<ScheduledTask> {
+        self.schedules.lock().clone()
+    }
+    /// Stops future runs; tasks already enqueued are left alone.
+    pub async fn cancel_schedule(&self, gid: Gid) -> bool {
+        log::debug!("downloader.cancel_schedule({gid})");
+        let mut schedules = self.schedules.lock();
+        let len = schedules.len();
+        schedules.retain(|task| task.gid != gid);
+        let removed = schedules.len() != len;
+        drop(schedules);
+        if removed {
+            self.save_schedules().await;
+        }
+        removed
+    }
+    /// Enqueues a fresh task for every schedule that is due; never returns.
+    pub async fn run_schedules(self: Arc<Self>) {
+        loop {
+            let now = OffsetDateTime::now_utc();
+            let due: Vec<_> = self
+                .schedules
+                .lock()
+                .iter_mut()
+                .filter(|task| task.next_run.is_some_and(|next| next <= now))
+                .map(|task| {
+                    task.next_run = task.spec.schedule.next_after(now);
+                    (task.gid, task.spec.add_options(now))
+                })
+                .collect();
+            for (gid, options) in due {
+                log::debug!("downloader.run_schedules(): Schedule {gid} is due");
+                match self.clone().add_task(options) {
+                    Ok(task) => {
+                        let mut schedules = self.schedules.lock();
+                        if let Some(schedule) = schedules.iter_mut().find(|task| task.gid == gid) {
+                            schedule.last_task = Some(task);
+                        }
+                    }
+                    Err(e) => log::error!("downloader.run_schedules(): Schedule {gid}: {e:?}"),
+                }
+            }
+            let next = self
+                .schedules
+                .lock()
+                .iter()
+                .filter_map(|task| task.next_run)
+                .min();
+            let wait = next
+                .map(|next| (next - OffsetDateTime::now_utc()).unsigned_abs())
+                .unwrap_or(Duration::MAX)
+                .clamp(Duration::from_secs(1), Duration::from_secs(60));
+            tokio::time::sleep(wait).await;
+        }
+    }
     pub fn remove(self: Arc<Self>, gid: Gid) -> Option<DownloadEntry> {
         log::debug!("downloader.remove({gid})");
         let mut list = self.list.lock();
@@ -110,6 +509,9 @@ impl Downloader {
         let entry = if let Some(pos) = pos {
             let removed = list.remove(pos);
             log::debug!("downloader.remove({gid}): {removed:?}");
+            if removed.is_complete() {
+                self.completed.lock().push(gid);
+            }
             log::debug!("downloader.remove({gid}): Aborting");
             removed.abort();
             log::debug!("downloader.remove({gid}): Aborted");
@@ -121,6 +523,75 @@ impl Downloader {
         self.run();
         entry
     }
+    /// Overrides the fields set in `changes` in the global config. Running
+    /// tasks that inherit one of them resume with the new value.
+    pub async fn change_global_options(
+        &self,
+        changes: &DownloadConfig,
+    ) -> Result<(), Vec<FieldError>> {
+        let fields: Arc<[_]> = changes.set_fields().into();
+        log::debug!("downloader.change_global_options({fields:?})");
+        validate(changes)?;
+        let config = changes.inherit(&*self.config.lock());
+        *self.config.lock() = config;
+        for entry in self.list.lock().iter() {
+            let mut inner = entry.inner.lock();
+            let own = inner.config.set_fields();
+            if inner.is_running()
+                && fields.iter().any(|field| !own.contains(field))
+                && inner.restart()
+            {
+                log::debug!(
+                    "downloader.change_global_options(): Restarting Gid {}",
+                    entry.gid
+                );
+            }
+        }
+        self.tx
+            .send(DownloaderEvent::ConfigChanged(fields))
+            .await
+            .unwrap();
+        Ok(())
+    }
+    /// Changes per-task options; running downloads continue with them.
+    /// Returns `Ok(false)` if no task has this `gid`.
+    pub fn change_options(
+        &self,
+        gid: Gid,
+        changes: &DownloadConfig,
+    ) -> Result<bool, Vec<FieldError>> {
+        log::debug!("downloader.change_options({gid})");
+        validate(changes)?;
+        let list = self.list.lock();
+        let Some(entry) = list.iter().find(|entry| entry.gid == gid) else {
+            return Ok(false);
+        };
+        entry.change_options(changes);
+        Ok(true)
+    }
+    /// Replaces the URL of a paused or failed task, or adds a mirror, and
+    /// resumes it with its progress if the new URL serves the same file.
+    pub async fn change_url(
+        self: Arc<Self>,
+        gid: Gid,
+        url: Url,
+        change: UrlChange,
+    ) -> Result<(), ChangeUrlError> {
+        log::debug!(
+            "downloader.change_url({gid}, {}, {change:?})",
+            redact_url(&url)
+        );
+        let entry = self
+            .list
+            .lock()
+            .iter()
+            .find(|entry| entry.gid == gid)
+            .cloned();
+        let entry = entry.ok_or(ChangeUrlError::NotFound)?;
+        entry.change_url(url, change).await?;
+        self.run();
+        Ok(())
+    }
     pub fn stop(self: Arc<Self>, gid: Gid) {
         log::debug!("downloader.stop({gid})");
         let mut list = self.list.lock();
@@ -130,8 +601,23 @@ impl Downloader {
             let entry = list.remove(pos);
             log::debug!("downloader.stop({gid}): {entry:?}");
             log::debug!("downloader.stop({gid}): Aborting");
-            entry.abort();
-            log::debug!("downloader.stop({gid}): Aborted");
+            let mut inner = entry.inner.lock();
+            let was_running = inner.is_running();
+            inner.abort();
+            let (config, path) = (inner.config(), inner.path.clone());
+            drop(inner);
+            log::debug!("downloader.stop({gid}): Aborted, was running = {was_running}");
+            if was_running {
+                hooks::fire(
+                    &config,
+                    HookPayload {
+                        gid: gid.to_string(),
+                        event: HookEvent::Pause,
+                        path,
+                        error: None,
+                    },
+                );
+            }
             list.push(entry);
             log::debug!("downloader.stop({gid}): Moved to end");
         }
@@ -146,3 +632,140 @@ impl Downloader {
             .sum()
     }
 }
+
+fn prerequisites_met(list: &[DownloadEntry], completed: &[Gid], entry: &DownloadEntry) -> bool {
+    entry.add_options.depends_on.iter().all(|gid| {
+        completed.contains(gid)
+            || list
+                .iter()
+                .find(|other| other.gid == *gid)
+                .is_some_and(DownloadEntry::is_complete)
+    })
+}
+
+/// Rejects prerequisites that do not exist and did not complete before being
+/// removed. A new task cannot close a cycle, since nothing depends on its
+/// fresh gid yet.
+fn check_dependencies(
+    list: &[DownloadEntry],
+    completed: &[Gid],
+    depends_on: &[Gid],
+) -> Result<(), AddTaskError> {
+    for &gid in depends_on {
+        if !completed.contains(&gid) && list.iter().all(|entry| entry.gid != gid) {
+            return Err(AddTaskError::UnknownPrerequisite(gid));
+        }
+    }
+    Ok(())
+}
+
+/// Fails or cancels waiting tasks whose prerequisites failed or were removed
+/// before completing, repeating until whole chains have been handled.
+fn resolve_dependencies(list: &mut Vec<DownloadEntry>, completed: &[Gid]) {
+    loop {
+        let mut cancelled = Vec::new();
+        let mut changed = false;
+        for entry in list.iter() {
+            if entry.is_running() || entry.error().is_some() {
+                continue;
+            }
+            let failed = entry.add_options.depends_on.iter().find(|gid| {
+                !completed.contains(gid)
+                    && list
+                        .iter()
+                        .find(|other| other.gid == **gid)
+                        .is_none_or(|other| other.error().is_some())
+            });
+            let Some(&prerequisite) = failed else {
+                continue;
+            };
+            log::debug!(
+                "downloader.run(): Prerequisite {prerequisite} of Gid {} failed",
+                entry.gid
+            );
+            changed = true;
+            match entry.add_options.on_dependency_error {
+                DependencyPolicy::Fail => entry.fail_dependency(prerequisite),
+                DependencyPolicy::Cancel => cancelled.push(entry.gid),
+            }
+        }
+        list.retain(|entry| !cancelled.contains(&entry.gid));
+        if !changed {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_conflict::ConflictOutcome;
+
+    fn entry(gid: Gid, depends_on: Vec<Gid>) -> DownloadEntry {
+        DownloadEntry::new(
+            gid,
+            AddOptions {
+                url: Url::parse("http://127.0.0.1:1/file").unwrap(),
+                mirrors: Vec::new(),
+                immediate_download: true,
+                config: DownloadConfig::empty(),
+                checksum: None,
+                out: None,
+                subdir: None,
+                depends_on,
+                on_dependency_error: DependencyPolicy::Fail,
+            },
+            Arc::new(SpinMutex::new(DownloadConfig::default())),
+            DiskSpace::new(),
+        )
+    }
+
+    #[test]
+    fn test_check_dependencies() {
+        let (a, b, done, gone) = (Gid::new(), Gid::new(), Gid::new(), Gid::new());
+        let list = [entry(a, vec![gone]), entry(b, vec![a])];
+        assert!(check_dependencies(&list, &[done], &[a, b, done]).is_ok());
+        assert!(matches!(
+            check_dependencies(&list, &[done], &[gone]),
+            Err(AddTaskError::UnknownPrerequisite(gid)) if gid == gone
+        ));
+    }
+
+    #[test]
+    fn test_removed_prerequisites() {
+        let (done, gone, waiting, orphan) = (Gid::new(), Gid::new(), Gid::new(), Gid::new());
+        let mut list = vec![entry(waiting, vec![done]), entry(orphan, vec![gone])];
+        resolve_dependencies(&mut list, &[done]);
+        assert!(list[0].error().is_none());
+        assert!(prerequisites_met(&list, &[done], &list[0]));
+        assert!(list[1].error().is_some());
+    }
+
+    #[test]
+    fn test_finished_tasks_are_not_restarted() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let downloader = Arc::new(Downloader::new(Arc::new(SpinMutex::new(
+                DownloadConfig::default(),
+            ))));
+            *downloader.parallelism.lock() = 3;
+            let (complete, unchanged, fresh) = (Gid::new(), Gid::new(), Gid::new());
+            let list = [
+                entry(complete, vec![]),
+                entry(unchanged, vec![]),
+                entry(fresh, vec![]),
+            ];
+            list[0].inner.lock().complete = true;
+            list[1].inner.lock().outcome = Some(ConflictOutcome::Unchanged);
+            downloader.list.lock().extend(list.iter().cloned());
+            downloader.clone().run();
+            assert!(!list[0].is_running());
+            assert!(!list[1].is_running());
+            assert!(list[2].is_running());
+            list[2].abort();
+        });
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{auth::Secret, config::DownloadConfig};
+use reqwest::{ClientBuilder, NoProxy, Proxy};
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+pub struct ProxyOptions {
+    pub http_proxy: Arc<str>,
+    pub https_proxy: Arc<str>,
+    pub all_proxy: Arc<str>,
+    pub no_proxy: Arc<str>,
+    pub proxy_user: Arc<str>,
+    pub proxy_passwd: Secret,
+}
+
+impl ProxyOptions {
+    pub fn new(config: &DownloadConfig) -> Self {
+        let mut all_proxy = config.all_proxy.clone().unwrap();
+        if all_proxy.is_empty() {
+            all_proxy = config.proxy.clone().unwrap();
+        }
+        Self {
+            http_proxy: config.http_proxy.clone().unwrap(),
+            https_proxy: config.https_proxy.clone().unwrap(),
+            all_proxy,
+            no_proxy: config.no_proxy.clone().unwrap(),
+            proxy_user: config.proxy_user.clone().unwrap(),
+            proxy_passwd: config.proxy_passwd.clone().unwrap(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.http_proxy.is_empty() && self.https_proxy.is_empty() && self.all_proxy.is_empty()
+    }
+
+    /// Takes the proxies from the environment variables reqwest would read,
+    /// merging their `NO_PROXY` into ours.
+    fn with_env(&self, env: impl Fn(&str) -> Option<String>) -> Self {
+        let var = |names: [&str; 2]| -> Arc<str> {
+            names
+                .into_iter()
+                .find_map(|name| env(name).filter(|value| !value.is_empty()))
+                .unwrap_or_default()
+                .into()
+        };
+        let mut no_proxy = self.no_proxy.to_string();
+        let env_no_proxy = var(["no_proxy", "NO_PROXY"]);
+        if !env_no_proxy.is_empty() {
+            no_proxy = format!("{no_proxy},{env_no_proxy}");
+        }
+        Self {
+            http_proxy: var(["http_proxy", "HTTP_PROXY"]),
+            https_proxy: var(["https_proxy", "HTTPS_PROXY"]),
+            all_proxy: var(["all_proxy", "ALL_PROXY"]),
+            no_proxy: no_proxy.into(),
+            ..self.clone()
+        }
+    }
+
+    /// Leaves the builder untouched when neither a proxy nor `no_proxy` is
+    /// configured, so the system proxy settings still apply. With only
+    /// `no_proxy`, the environment proxies are set explicitly so that it
+    /// applies to them too.
+    pub fn apply(&self, mut client: ClientBuilder) -> Result<ClientBuilder, reqwest::Error> {
+        let options = if !self.is_empty() {
+            self.clone()
+        } else if !self.no_proxy.is_empty() {
+            self.with_env(|name| std::env::var(name).ok())
+        } else {
+            return Ok(client);
+        };
+        if options.is_empty() {
+            return Ok(client);
+        }
+        let rules: [(&str, fn(String) -> reqwest::Result<Proxy>); 3] = [
+            (&options.http_proxy, Proxy::http),
+            (&options.https_proxy, Proxy::https),
+            (&options.all_proxy, Proxy::all),
+        ];
+        for (url, proxy) in rules {
+            if url.is_empty() {
+                continue;
+            }
+            let mut proxy =
+                proxy(url.to_string())?.no_proxy(NoProxy::from_string(&options.no_proxy));
+            if !options.proxy_user.is_empty() {
+                proxy = proxy.basic_auth(&options.proxy_user, options.proxy_passwd.expose());
+            }
+            client = client.proxy(proxy);
+        }
+        Ok(client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proxy_options() {
+        let legacy = DownloadConfig {
+            proxy: Some(Arc::from("http://legacy:3128")),
+            ..DownloadConfig::default()
+        };
+        assert_eq!(&*ProxyOptions::new(&legacy).all_proxy, "http://legacy:3128");
+        let both = DownloadConfig {
+            all_proxy: Some(Arc::from("http://new:3128")),
+            ..legacy
+        };
+        assert_eq!(&*ProxyOptions::new(&both).all_proxy, "http://new:3128");
+
+        let config = DownloadConfig {
+            no_proxy: Some(Arc::from("internal.example.com")),
+            ..DownloadConfig::default()
+        };
+        let options = ProxyOptions::new(&config).with_env(|name| match name {
+            "HTTPS_PROXY" => Some("http://env:3128".into()),
+            "http_proxy" => Some(String::new()),
+            "NO_PROXY" => Some("localhost".into()),
+            _ => None,
+        });
+        assert_eq!(&*options.https_proxy, "http://env:3128");
+        assert!(options.http_proxy.is_empty());
+        assert_eq!(&*options.no_proxy, "internal.example.com,localhost");
+    }
+}
@@ -1,80 +1,155 @@
+use crate::{
+    auth,
+    config::DownloadConfig,
+    cookie_jar::CookieJar,
+    dns::DnsOptions,
+    ftp::{FtpError, FtpOptions, FtpPuller, is_ftp},
+    proxy::ProxyOptions,
+    tls::{self, TlsError},
+};
 use fast_down::{
-    FileId, PullResult, PullStream, RandPuller, SeqPuller,
-    http::{HttpError, HttpPuller},
+    FileId, PullResult, PullStream, RandPuller, SeqPuller, UrlInfo,
+    http::{HttpError, HttpPuller, Prefetch},
+};
+use futures_util::{TryStreamExt, future::Either};
+use reqwest::{
+    Client, ClientBuilder, Response,
+    header::{AUTHORIZATION, COOKIE, HeaderMap, HeaderValue, PROXY_AUTHORIZATION},
 };
-use reqwest::{Client, ClientBuilder, Proxy, Response, header::HeaderMap};
 use spin::mutex::SpinMutex;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 use url::Url;
 
-pub fn build_client(
-    headers: &HeaderMap,
-    proxy: &str,
-    accept_invalid_certs: bool,
-    accept_invalid_hostnames: bool,
-) -> Result<reqwest::Client, reqwest::Error> {
-    let mut client = ClientBuilder::new()
-        .default_headers(headers.clone())
-        .danger_accept_invalid_certs(accept_invalid_certs)
-        .danger_accept_invalid_hostnames(accept_invalid_hostnames)
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    pub headers: Arc<HeaderMap>,
+    pub proxy: ProxyOptions,
+    pub accept_invalid_certs: bool,
+    pub accept_invalid_hostnames: bool,
+    pub authorization: Option<HeaderValue>,
+    pub cookie_jar: CookieJar,
+    pub dns: DnsOptions,
+    pub tls: Option<Arc<rustls::ClientConfig>>,
+    /// Set for `ftp://` and `ftps://` URLs
+    pub ftp: Option<Arc<FtpOptions>>,
+}
+
+impl ClientOptions {
+    pub fn new(config: &DownloadConfig, url: &Url) -> Result<Self, TlsError> {
+        Ok(Self {
+            headers: config.headers.clone().unwrap(),
+            proxy: ProxyOptions::new(config),
+            accept_invalid_certs: config.accept_invalid_certs.unwrap(),
+            accept_invalid_hostnames: config.accept_invalid_hostnames.unwrap(),
+            authorization: auth::authorization(config, url),
+            cookie_jar: config.cookie_jar.clone().unwrap(),
+            dns: DnsOptions::new(config),
+            tls: tls::client_config(config)?.map(Arc::new),
+            ftp: is_ftp(url)
+                .then(|| FtpOptions::new(config, url))
+                .transpose()?
+                .map(Arc::new),
+        })
+    }
+}
+
+/// Credentials are sent as default headers; reqwest drops `Authorization`
+/// and `Cookie` when a redirect leaves the original host.
+pub fn build_client(options: &ClientOptions) -> Result<reqwest::Client, reqwest::Error> {
+    let mut headers = options.headers.as_ref().clone();
+    for (name, value) in headers.iter_mut() {
+        if name == AUTHORIZATION || name == PROXY_AUTHORIZATION || name == COOKIE {
+            value.set_sensitive(true);
+        }
+    }
+    if let Some(authorization) = &options.authorization {
+        headers.insert(AUTHORIZATION, authorization.clone());
+    }
+    let client = ClientBuilder::new()
+        .default_headers(headers)
+        .cookie_provider(options.cookie_jar.store())
+        .danger_accept_invalid_certs(options.accept_invalid_certs)
+        .danger_accept_invalid_hostnames(options.accept_invalid_hostnames)
         .http2_adaptive_window(true)
         .brotli(true)
         .gzip(true)
         .deflate(true)
         .zstd(true);
-    if !proxy.is_empty() {
-        client = client.proxy(Proxy::all(proxy)?);
+    let mut client = options.dns.apply(client);
+    if let Some(tls) = &options.tls {
+        client = client.use_preconfigured_tls(tls.as_ref().clone());
     }
-    let client = client.build()?;
+    let client = options.proxy.apply(client)?.build()?;
     Ok(client)
 }
 
+#[derive(Debug)]
+pub enum PullerError {
+    Http(HttpError<Client>),
+    Ftp(FtpError),
+}
+
+/// Prefetches over HTTP or FTP depending on the URL scheme; only HTTP leaves
+/// a response for the first puller to reuse.
+pub async fn prefetch(
+    client: &Client,
+    options: &ClientOptions,
+    url: Url,
+) -> Result<(UrlInfo, Option<Response>), (PullerError, Option<Duration>)> {
+    match &options.ftp {
+        Some(ftp) => crate::ftp::prefetch(&url, ftp)
+            .await
+            .map(|info| (info, None))
+            .map_err(|e| (PullerError::Ftp(e), None)),
+        None => client
+            .prefetch(url)
+            .await
+            .map(|(info, resp)| (info, Some(resp)))
+            .map_err(|(e, retry)| (PullerError::Http(e), retry)),
+    }
+}
+
+#[derive(Debug)]
+enum Inner {
+    Http(HttpPuller<Client>),
+    Ftp(FtpPuller),
+}
+
 #[derive(Debug)]
 pub struct FastDownPuller {
-    inner: HttpPuller<Client>,
-    headers: Arc<HeaderMap>,
-    proxy: Arc<str>,
+    inner: Inner,
+    client_options: ClientOptions,
     url: Arc<Url>,
     multiplexing: bool,
-    accept_invalid_certs: bool,
-    accept_invalid_hostnames: bool,
     file_id: FileId,
     resp: Option<Arc<SpinMutex<Option<Response>>>>,
 }
 
-pub struct FastDownPullerOptions<'a> {
+pub struct FastDownPullerOptions {
     pub url: Url,
-    pub headers: Arc<HeaderMap>,
-    pub proxy: &'a str,
+    pub client_options: ClientOptions,
     pub multiplexing: bool,
-    pub accept_invalid_certs: bool,
-    pub accept_invalid_hostnames: bool,
     pub file_id: FileId,
     pub resp: Option<Arc<SpinMutex<Option<Response>>>>,
 }
 
 impl FastDownPuller {
     pub fn new(option: FastDownPullerOptions) -> Result<Self, reqwest::Error> {
-        let client = build_client(
-            &option.headers,
-            option.proxy,
-            option.accept_invalid_certs,
-            option.accept_invalid_hostnames,
-        )?;
-        Ok(Self {
-            inner: HttpPuller::new(
+        let inner = match &option.client_options.ftp {
+            Some(ftp) => Inner::Ftp(FtpPuller::new(option.url.clone(), ftp.clone())),
+            None => Inner::Http(HttpPuller::new(
                 option.url.clone(),
-                client,
+                build_client(&option.client_options)?,
                 option.resp.clone(),
                 option.file_id.clone(),
-            ),
+            )),
+        };
+        Ok(Self {
+            inner,
             resp: option.resp,
-            headers: option.headers,
-            proxy: Arc::from(option.proxy),
+            client_options: option.client_options,
             url: Arc::new(option.url),
             multiplexing: option.multiplexing,
-            accept_invalid_certs: option.accept_invalid_certs,
-            accept_invalid_hostnames: option.accept_invalid_hostnames,
             file_id: option.file_id,
         })
     }
@@ -83,47 +158,69 @@ impl FastDownPuller {
 impl Clone for FastDownPuller {
     fn clone(&self) -> Self {
         Self {
-            inner: if !self.multiplexing
-                && let Ok(client) = build_client(
-                    &self.headers,
-                    &self.proxy,
-                    self.accept_invalid_certs,
-                    self.accept_invalid_hostnames,
-                ) {
-                HttpPuller::new(
-                    self.url.as_ref().clone(),
-                    client,
-                    self.resp.clone(),
-                    self.file_id.clone(),
-                )
-            } else {
-                self.inner.clone()
+            inner: match &self.inner {
+                Inner::Http(inner) => {
+                    if !self.multiplexing
+                        && let Ok(client) = build_client(&self.client_options)
+                    {
+                        Inner::Http(HttpPuller::new(
+                            self.url.as_ref().clone(),
+                            client,
+                            self.resp.clone(),
+                            self.file_id.clone(),
+                        ))
+                    } else {
+                        Inner::Http(inner.clone())
+                    }
+                }
+                Inner::Ftp(inner) => Inner::Ftp(inner.clone()),
             },
             resp: self.resp.clone(),
-            headers: self.headers.clone(),
-            proxy: self.proxy.clone(),
+            client_options: self.client_options.clone(),
             url: self.url.clone(),
             multiplexing: self.multiplexing,
-            accept_invalid_certs: self.accept_invalid_certs,
-            accept_invalid_hostnames: self.accept_invalid_hostnames,
             file_id: self.file_id.clone(),
         }
     }
 }
 
 impl RandPuller for FastDownPuller {
-    type Error = HttpError<Client>;
+    type Error = PullerError;
     async fn pull(
         &mut self,
         range: &fast_down::ProgressEntry,
     ) -> PullResult<Self::Error, impl PullStream<Self::Error>> {
-        RandPuller::pull(&mut self.inner, range).await
+        match &mut self.inner {
+            Inner::Http(inner) => RandPuller::pull(inner, range)
+                .await
+                .map(|stream| {
+                    Either::Left(stream.map_err(|(e, retry)| (PullerError::Http(e), retry)))
+                })
+                .map_err(|(e, retry)| (PullerError::Http(e), retry)),
+            Inner::Ftp(inner) => inner
+                .pull(range.start, Some(range.end - range.start))
+                .await
+                .map(|stream| Either::Right(stream.map_err(|e| (PullerError::Ftp(e), None))))
+                .map_err(|e| (PullerError::Ftp(e), None)),
+        }
     }
 }
 
 impl SeqPuller for FastDownPuller {
-    type Error = HttpError<Client>;
+    type Error = PullerError;
     async fn pull(&mut self) -> PullResult<Self::Error, impl PullStream<Self::Error>> {
-        SeqPuller::pull(&mut self.inner).await
+        match &mut self.inner {
+            Inner::Http(inner) => SeqPuller::pull(inner)
+                .await
+                .map(|stream| {
+                    Either::Left(stream.map_err(|(e, retry)| (PullerError::Http(e), retry)))
+                })
+                .map_err(|(e, retry)| (PullerError::Http(e), retry)),
+            Inner::Ftp(inner) => inner
+                .pull(0, None)
+                .await
+                .map(|stream| Either::Right(stream.map_err(|e| (PullerError::Ftp(e), None))))
+                .map_err(|e| (PullerError::Ftp(e), None)),
+        }
     }
 }
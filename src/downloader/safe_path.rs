@@ -0,0 +1,43 @@
+use std::path::{Component, Path};
+use tokio::io;
+
+/// Rejects paths that could escape the directory they are joined onto:
+/// absolute paths, drive prefixes and `..` components.
+pub fn check_relative(path: &Path) -> io::Result<()> {
+    let mut has_name = false;
+    for component in path.components() {
+        match component {
+            Component::Normal(_) => has_name = true,
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{path:?} must stay inside save_dir"),
+                ));
+            }
+        }
+    }
+    if !has_name {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{path:?} is empty"),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_relative() {
+        assert!(check_relative(Path::new("a/b.txt")).is_ok());
+        assert!(check_relative(Path::new("./a")).is_ok());
+        assert!(check_relative(Path::new("../a")).is_err());
+        assert!(check_relative(Path::new("a/../../b")).is_err());
+        assert!(check_relative(Path::new("/etc/passwd")).is_err());
+        assert!(check_relative(Path::new("")).is_err());
+        assert!(check_relative(Path::new(".")).is_err());
+    }
+}
@@ -0,0 +1,343 @@
+use crate::{
+    config::DownloadConfig,
+    entry::{AddOptions, DependencyPolicy},
+    file_conflict::FileConflict,
+};
+use aria2_gid::Gid;
+use serde_json::{Value, json};
+use std::{fmt, path::PathBuf, time::Duration};
+use time::{Date, Month, OffsetDateTime};
+use tokio::{fs, io};
+use url::Url;
+
+/// A five-field cron expression (`minute hour day month weekday`), in UTC.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+fn parse_field(field: &str, min: u64, max: u64) -> Option<u64> {
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().ok().filter(|s| *s > 0)?),
+            None => (part, 1),
+        };
+        let (lo, hi) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((lo, hi)) => (lo.parse().ok()?, hi.parse().ok()?),
+            None if part.contains('/') => (range.parse().ok()?, max),
+            None => {
+                let value = range.parse().ok()?;
+                (value, value)
+            }
+        };
+        if lo < min || hi > max || lo > hi {
+            return None;
+        }
+        for value in (lo..=hi).step_by(step) {
+            mask |= 1 << value;
+        }
+    }
+    Some(mask)
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> Option<Self> {
+        let fields: Vec<_> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return None;
+        };
+        let mut weekdays = parse_field(weekday, 0, 7)?;
+        if weekdays & 1 << 7 != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Some(Self {
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)?,
+            days: parse_field(day, 1, 31)?,
+            months: parse_field(month, 1, 12)?,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+
+    fn matches_day(&self, date: Date) -> bool {
+        let day = self.days & 1 << date.day() != 0;
+        let weekday = self.weekdays & 1 << date.weekday().number_days_from_sunday() != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// The first matching minute strictly after `after`, looking ahead at
+    /// most five years.
+    pub fn next_after(&self, after: OffsetDateTime) -> Option<OffsetDateTime> {
+        let mut t =
+            after.replace_nanosecond(0).ok()?.replace_second(0).ok()? + time::Duration::minutes(1);
+        let limit = t + time::Duration::days(5 * 366);
+        while t < limit {
+            let date = t.date();
+            if self.months & 1 << u8::from(date.month()) == 0 {
+                let (year, month) = match date.month() {
+                    Month::December => (date.year() + 1, Month::January),
+                    month => (date.year(), month.next()),
+                };
+                t = Date::from_calendar_date(year, month, 1)
+                    .ok()?
+                    .midnight()
+                    .assume_utc();
+            } else if !self.matches_day(date) {
+                t = date.next_day()?.midnight().assume_utc();
+            } else if self.hours & 1 << t.hour() == 0 {
+                t = t.replace_minute(0).ok()? + time::Duration::hours(1);
+            } else if self.minutes & 1 << t.minute() == 0 {
+                t += time::Duration::minutes(1);
+            } else {
+                return Some(t);
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleKind {
+    Interval(Duration),
+    Cron(Cron),
+}
+
+/// Parsed from `@every 6h`, `@hourly`, `@daily`, `@weekly`, `@monthly` or a
+/// five-field cron expression; the original text is kept for persistence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    pub kind: ScheduleKind,
+    text: String,
+}
+
+impl Schedule {
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let kind = if let Some(every) = text.strip_prefix("@every ") {
+            let every = every.trim();
+            let unit = match every.chars().last()? {
+                's' => 1,
+                'm' => 60,
+                'h' => 60 * 60,
+                'd' => 24 * 60 * 60,
+                _ => return None,
+            };
+            let count: u64 = every[..every.len() - 1].parse().ok().filter(|n| *n > 0)?;
+            ScheduleKind::Interval(Duration::from_secs(count.checked_mul(unit)?))
+        } else {
+            let expr = match text {
+                "@hourly" => "0 * * * *",
+                "@daily" | "@midnight" => "0 0 * * *",
+                "@weekly" => "0 0 * * 0",
+                "@monthly" => "0 0 1 * *",
+                expr => expr,
+            };
+            ScheduleKind::Cron(Cron::parse(expr)?)
+        };
+        Some(Self {
+            kind,
+            text: text.to_string(),
+        })
+    }
+
+    pub fn next_after(&self, after: OffsetDateTime) -> Option<OffsetDateTime> {
+        match &self.kind {
+            ScheduleKind::Interval(interval) => Some(after + *interval),
+            ScheduleKind::Cron(cron) => cron.next_after(after),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Expands `%Y`, `%m`, `%d`, `%H`, `%M`, `%S` and `%%`; other sequences are
+/// kept as they are.
+pub fn render_template(template: &str, t: OffsetDateTime) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", t.year())),
+            Some('m') => out.push_str(&format!("{:02}", u8::from(t.month()))),
+            Some('d') => out.push_str(&format!("{:02}", t.day())),
+            Some('H') => out.push_str(&format!("{:02}", t.hour())),
+            Some('M') => out.push_str(&format!("{:02}", t.minute())),
+            Some('S') => out.push_str(&format!("{:02}", t.second())),
+            Some('%') => out.push('%'),
+            Some(other) => {
+                out.push('%');
+                out.push(other);
+            }
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+/// What a scheduled task enqueues every time it fires.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduleSpec {
+    pub schedule: Schedule,
+    pub url: Url,
+    pub mirrors: Vec<Url>,
+    /// File name template, see [`render_template`]
+    pub out: Option<String>,
+    /// Directory template, see [`render_template`]
+    pub subdir: Option<String>,
+    pub save_dir: Option<PathBuf>,
+    /// Only download when the remote file changed, see [`FileConflict::Update`]
+    pub update: bool,
+}
+
+impl ScheduleSpec {
+    pub fn add_options(&self, now: OffsetDateTime) -> AddOptions {
+        let mut config = DownloadConfig::empty();
+        config.save_dir = self.save_dir.as_deref().map(Into::into);
+        if self.update {
+            config.file_conflict = Some(FileConflict::Update);
+        }
+        AddOptions {
+            url: self.url.clone(),
+            mirrors: self.mirrors.clone(),
+            immediate_download: true,
+            config,
+            checksum: None,
+            out: self
+                .out
+                .as_deref()
+                .map(|out| render_template(out, now).into()),
+            subdir: self
+                .subdir
+                .as_deref()
+                .map(|subdir| render_template(subdir, now).into()),
+            depends_on: Vec::new(),
+            on_dependency_error: DependencyPolicy::Fail,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "schedule": self.schedule.to_string(),
+            "url": self.url.as_str(),
+            "mirrors": self.mirrors.iter().map(Url::as_str).collect::<Vec<_>>(),
+            "out": self.out,
+            "subdir": self.subdir,
+            "save_dir": self.save_dir.as_ref().map(|dir| dir.to_string_lossy()),
+            "update": self.update,
+        })
+    }
+
+    pub fn from_json(value: &Value) -> Option<Self> {
+        let text = |key: &str| value[key].as_str().map(str::to_string);
+        Some(Self {
+            schedule: Schedule::parse(value["schedule"].as_str()?)?,
+            url: Url::parse(value["url"].as_str()?).ok()?,
+            mirrors: match value["mirrors"].as_array() {
+                Some(mirrors) => mirrors
+                    .iter()
+                    .map(|mirror| Url::parse(mirror.as_str()?).ok())
+                    .collect::<Option<_>>()?,
+                None => Vec::new(),
+            },
+            out: text("out"),
+            subdir: text("subdir"),
+            save_dir: text("save_dir").map(Into::into),
+            update: value["update"].as_bool().unwrap_or(false),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScheduledTask {
+    pub gid: Gid,
+    pub spec: ScheduleSpec,
+    pub next_run: Option<OffsetDateTime>,
+    /// The task enqueued by the latest run
+    pub last_task: Option<Gid>,
+}
+
+pub async fn load_schedules(path: &std::path::Path) -> io::Result<Vec<ScheduleSpec>> {
+    let content = match fs::read(path).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let value: Value = serde_json::from_slice(&content)?;
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|spec| {
+            ScheduleSpec::from_json(spec).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid schedule {spec}"),
+                )
+            })
+        })
+        .collect()
+}
+
+pub async fn save_schedules(path: &std::path::Path, specs: &[ScheduleSpec]) -> io::Result<()> {
+    let value = Value::Array(specs.iter().map(ScheduleSpec::to_json).collect());
+    let mut tmp = path.as_os_str().to_os_string();
+    tmp.push(".tmp");
+    fs::write(&tmp, value.to_string()).await?;
+    fs::rename(&tmp, path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_cron_next_after() {
+        let cron = Schedule::parse("30 3 * * 1-5").unwrap();
+        // Saturday
+        let t = datetime!(2024-06-01 12:00 UTC);
+        assert_eq!(cron.next_after(t), Some(datetime!(2024-06-03 03:30 UTC)));
+        let every = Schedule::parse("*/15 * * * *").unwrap();
+        assert_eq!(
+            every.next_after(datetime!(2024-12-31 23:50:10 UTC)),
+            Some(datetime!(2025-01-01 00:00 UTC))
+        );
+        assert_eq!(
+            Schedule::parse("@every 6h").unwrap().kind,
+            ScheduleKind::Interval(Duration::from_secs(6 * 60 * 60))
+        );
+        assert!(Schedule::parse("61 * * * *").is_none());
+        assert!(Schedule::parse("* * *").is_none());
+    }
+
+    #[test]
+    fn test_render_template() {
+        let t = datetime!(2024-06-01 03:04:05 UTC);
+        assert_eq!(
+            render_template("nightly-%Y%m%d-%H%M%S.iso", t),
+            "nightly-20240601-030405.iso"
+        );
+        assert_eq!(render_template("100%% %q", t), "100% %q");
+    }
+}
@@ -0,0 +1,222 @@
+use crate::config::DownloadConfig;
+use p12_keystore::KeyStore;
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+    client::{
+        WebPkiServerVerifier,
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    },
+    crypto::{CryptoProvider, ring, verify_tls12_signature, verify_tls13_signature},
+    pki_types::{
+        CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime, pem::PemObject,
+    },
+};
+use sha2::{Digest, Sha256};
+use std::{path::Path, sync::Arc};
+
+#[derive(Debug)]
+pub enum TlsError {
+    Pem(Arc<Path>, rustls::pki_types::pem::Error),
+    Pkcs12(Arc<Path>, String),
+    Io(Arc<Path>, std::io::Error),
+    NoPrivateKey(Arc<Path>),
+    InvalidPin(Arc<str>),
+    Rustls(rustls::Error),
+    Verifier(rustls::client::VerifierBuilderError),
+}
+
+/// Parses a SHA-256 certificate fingerprint, with or without `:` separators.
+pub fn parse_pin(pin: &str) -> Option<[u8; 32]> {
+    let hex: String = pin.chars().filter(|c| *c != ':').collect();
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut pin = [0; 32];
+    for (i, byte) in pin.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(pin)
+}
+
+fn is_pkcs12(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("p12") || ext.eq_ignore_ascii_case("pfx"))
+}
+
+fn load_identity(
+    cert: &Arc<Path>,
+    key: &Arc<Path>,
+    passwd: &str,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), TlsError> {
+    if is_pkcs12(cert) {
+        let data = std::fs::read(cert).map_err(|e| TlsError::Io(cert.clone(), e))?;
+        let keystore = KeyStore::from_pkcs12(&data, passwd)
+            .map_err(|e| TlsError::Pkcs12(cert.clone(), e.to_string()))?;
+        let (_, chain) = keystore
+            .private_key_chain()
+            .ok_or_else(|| TlsError::NoPrivateKey(cert.clone()))?;
+        let certs = chain
+            .chain()
+            .iter()
+            .map(|cert| CertificateDer::from(cert.as_der().to_vec()))
+            .collect();
+        let key = PrivatePkcs8KeyDer::from(chain.key().to_vec()).into();
+        return Ok((certs, key));
+    }
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| TlsError::Pem(cert.clone(), e))?;
+    let key_path = if key.as_os_str().is_empty() {
+        cert
+    } else {
+        key
+    };
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| match e {
+        rustls::pki_types::pem::Error::NoItemsFound => TlsError::NoPrivateKey(key_path.clone()),
+        e => TlsError::Pem(key_path.clone(), e),
+    })?;
+    Ok((certs, key))
+}
+
+/// Returns `None` when no TLS option is set, leaving reqwest's own TLS setup in place.
+pub fn client_config(config: &DownloadConfig) -> Result<Option<ClientConfig>, TlsError> {
+    let client_cert = config.client_cert.as_ref().unwrap();
+    if config.ca_certs.as_ref().unwrap().is_empty()
+        && client_cert.as_os_str().is_empty()
+        && config.pinned_certs.as_ref().unwrap().is_empty()
+    {
+        return Ok(None);
+    }
+    let mut tls = build_config(config)?;
+    tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Some(tls))
+}
+
+/// The TLS setup for protocols reqwest does not handle, such as FTPS.
+pub fn build_config(config: &DownloadConfig) -> Result<ClientConfig, TlsError> {
+    let ca_certs = config.ca_certs.clone().unwrap();
+    let client_cert = config.client_cert.clone().unwrap();
+    let pinned_certs = config.pinned_certs.clone().unwrap();
+    let provider = Arc::new(ring::default_provider());
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    for path in ca_certs.iter() {
+        for cert in
+            CertificateDer::pem_file_iter(path).map_err(|e| TlsError::Pem(path.clone(), e))?
+        {
+            let cert = cert.map_err(|e| TlsError::Pem(path.clone(), e))?;
+            roots.add(cert).map_err(TlsError::Rustls)?;
+        }
+    }
+    let pins = pinned_certs
+        .iter()
+        .map(|pin| parse_pin(pin).ok_or_else(|| TlsError::InvalidPin(pin.clone())))
+        .collect::<Result<Vec<_>, _>>()?;
+    let verifier = Arc::new(CertVerifier {
+        inner: WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+            .build()
+            .map_err(TlsError::Verifier)?,
+        provider: provider.clone(),
+        pins,
+        accept_invalid_certs: config.accept_invalid_certs.unwrap(),
+        accept_invalid_hostnames: config.accept_invalid_hostnames.unwrap(),
+    });
+    let builder = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(TlsError::Rustls)?
+        .dangerous()
+        .with_custom_certificate_verifier(verifier);
+    let tls = if client_cert.as_os_str().is_empty() {
+        builder.with_no_client_auth()
+    } else {
+        let (certs, key) = load_identity(
+            &client_cert,
+            config.client_key.as_ref().unwrap(),
+            config.client_cert_passwd.as_ref().unwrap().expose(),
+        )?;
+        builder
+            .with_client_auth_cert(certs, key)
+            .map_err(TlsError::Rustls)?
+    };
+    Ok(tls)
+}
+
+#[derive(Debug)]
+struct CertVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    provider: Arc<CryptoProvider>,
+    pins: Vec<[u8; 32]>,
+    accept_invalid_certs: bool,
+    accept_invalid_hostnames: bool,
+}
+
+impl ServerCertVerifier for CertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if !self.accept_invalid_certs {
+            match self.inner.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+            ) {
+                Ok(_) => {}
+                Err(rustls::Error::InvalidCertificate(
+                    CertificateError::NotValidForName
+                    | CertificateError::NotValidForNameContext { .. },
+                )) if self.accept_invalid_hostnames => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if !self.pins.is_empty() {
+            let fingerprint: [u8; 32] = Sha256::digest(end_entity.as_ref()).into();
+            if !self.pins.contains(&fingerprint) {
+                return Err(rustls::Error::InvalidCertificate(
+                    CertificateError::ApplicationVerificationFailure,
+                ));
+            }
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
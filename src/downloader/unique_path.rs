@@ -1,23 +1,139 @@
+use crate::{control::control_path, fnv::fnv1a};
+use spin::mutex::SpinMutex;
 use std::path::{Path, PathBuf};
 use tokio::{fs, io};
 
+/// Target paths picked by running or unfinished tasks of this process.
+static CLAIMED: SpinMutex<Vec<PathBuf>> = SpinMutex::new(Vec::new());
+
+/// Keeps other tasks from picking the same target path until dropped.
+#[derive(Debug)]
+pub struct PathClaim(PathBuf);
+
+impl PathClaim {
+    /// Returns `None` if another task holds `path`.
+    pub fn new(path: &Path) -> Option<Self> {
+        let mut claimed = CLAIMED.lock();
+        if claimed.iter().any(|other| other == path) {
+            return None;
+        }
+        claimed.push(path.into());
+        Some(Self(path.into()))
+    }
+}
+
+impl Drop for PathClaim {
+    fn drop(&mut self) {
+        CLAIMED.lock().retain(|other| *other != self.0);
+    }
+}
+
+fn numbered(path: &Path, i: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default();
+    let ext = path.extension().unwrap_or_default();
+    let mut new_name = stem.to_os_string();
+    new_name.push(" (");
+    new_name.push(i.to_string());
+    new_name.push(").");
+    new_name.push(ext);
+    path.with_file_name(new_name)
+}
+
 pub async fn gen_unique_path(path: impl AsRef<Path>) -> io::Result<PathBuf> {
     let path = path.as_ref();
     if !fs::try_exists(path).await? {
         return Ok(path.into());
     }
-    let stem = path.file_stem().unwrap_or_default();
-    let ext = path.extension().unwrap_or_default();
     for i in 1.. {
-        let mut new_name = stem.to_os_string();
-        new_name.push(" (");
-        new_name.push(i.to_string());
-        new_name.push(").");
-        new_name.push(ext);
-        let new_path = path.with_file_name(new_name);
+        let new_path = numbered(path, i);
         if !fs::try_exists(&new_path).await? {
             return Ok(new_path);
         }
     }
     unreachable!()
 }
+
+/// Like [`gen_unique_path`], but also skips names another task claimed and
+/// names whose `.part` file exists, unless a control file marks that as a
+/// resumable leftover. `part_dir` is `None` when `.part` files are off.
+pub async fn claim_unique_path(
+    path: &Path,
+    part_dir: Option<&Path>,
+) -> io::Result<(PathBuf, PathClaim)> {
+    for i in 0.. {
+        let candidate = match i {
+            0 => path.to_path_buf(),
+            i => numbered(path, i),
+        };
+        if fs::try_exists(&candidate).await? {
+            continue;
+        }
+        if let Some(part_dir) = part_dir {
+            let part = part_path(&candidate, part_dir);
+            if fs::try_exists(&part).await? && !fs::try_exists(control_path(&part)).await? {
+                continue;
+            }
+        }
+        if let Some(claim) = PathClaim::new(&candidate) {
+            return Ok((candidate, claim));
+        }
+    }
+    unreachable!()
+}
+
+/// Where a download is written until it completes; `temp_dir` should be on the
+/// same filesystem as `path` so the final rename stays atomic. In a shared
+/// `temp_dir` the name carries a hash of `path`, so that files of the same
+/// name from different directories do not collide.
+pub fn part_path(path: &Path, temp_dir: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    if temp_dir.as_os_str().is_empty() {
+        name.push(".part");
+        path.with_file_name(name)
+    } else {
+        let hash = fnv1a(path.as_os_str().as_encoded_bytes());
+        name.push(format!(".{hash:016x}.part"));
+        temp_dir.join(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_part_path() {
+        let (a, b) = (Path::new("/data/a/file.iso"), Path::new("/data/b/file.iso"));
+        assert_eq!(
+            part_path(a, Path::new("")),
+            Path::new("/data/a/file.iso.part")
+        );
+        let shared = Path::new("/tmp/parts");
+        assert_ne!(part_path(a, shared), part_path(b, shared));
+        assert_eq!(part_path(a, shared), part_path(a, shared));
+        assert!(part_path(a, shared).starts_with(shared));
+    }
+
+    #[test]
+    fn test_claim_unique_path() {
+        let dir = std::env::temp_dir().join(format!("claim-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("file.bin");
+        std::fs::write(part_path(&path, Path::new("")), b"partial").unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (first, _first_claim) = rt
+            .block_on(claim_unique_path(&path, Some(Path::new(""))))
+            .unwrap();
+        assert_eq!(first, dir.join("file (1).bin"));
+        let (second, second_claim) = rt
+            .block_on(claim_unique_path(&path, Some(Path::new(""))))
+            .unwrap();
+        assert_eq!(second, dir.join("file (2).bin"));
+        drop(second_claim);
+        assert!(PathClaim::new(&second).is_some());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{config::DownloadConfig, dns::parse_resolve_entry, tls::parse_pin};
+use reqwest::Proxy;
+use std::{
+    fs,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Makes probe names unique within the process; the time tells processes
+/// apart even when they share a PID, as in containers.
+static PROBE: AtomicU64 = AtomicU64::new(0);
+
+/// A config field holding a value that cannot work.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    fn new(field: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            field,
+            message: message.into(),
+        }
+    }
+}
+
+fn check_save_dir(dir: &Path) -> Result<(), String> {
+    let metadata = fs::metadata(dir).map_err(|e| format!("{dir:?}: {e}"))?;
+    if !metadata.is_dir() {
+        return Err(format!("{dir:?} is not a directory"));
+    }
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |t| t.as_nanos());
+    let probe = dir.join(format!(
+        ".write-test-{}-{nanos}-{}",
+        std::process::id(),
+        PROBE.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .map_err(|e| format!("{dir:?} is not writable: {e}"))?;
+    let _ = fs::remove_file(probe);
+    Ok(())
+}
+
+/// Checks the fields that are set; unset fields are inherited and checked
+/// where they are set.
+pub fn validate(config: &DownloadConfig) -> Result<(), Vec<FieldError>> {
+    let mut errors = Vec::new();
+    let proxies = [
+        ("http_proxy", &config.http_proxy),
+        ("https_proxy", &config.https_proxy),
+        ("all_proxy", &config.all_proxy),
+        ("proxy", &config.proxy),
+    ];
+    for (field, proxy) in proxies {
+        if let Some(proxy) = proxy.as_deref()
+            && !proxy.is_empty()
+            && let Err(e) = Proxy::all(proxy)
+        {
+            errors.push(FieldError::new(
+                field,
+                format!("invalid proxy {proxy:?}: {e}"),
+            ));
+        }
+    }
+    if let Some(headers) = config.headers.as_deref() {
+        for (name, value) in headers {
+            if value
+                .as_bytes()
+                .iter()
+                .any(|b| matches!(b, b'\r' | b'\n' | 0))
+            {
+                errors.push(FieldError::new(
+                    "headers",
+                    format!("value of {name} contains a control character"),
+                ));
+            }
+        }
+    }
+    if config.write_buffer_size == Some(0) {
+        errors.push(FieldError::new("write_buffer_size", "must not be 0"));
+    }
+    if config.write_queue_cap == Some(0) {
+        errors.push(FieldError::new("write_queue_cap", "must not be 0"));
+    }
+    if let Some(dir) = config.save_dir.as_deref()
+        && let Err(e) = check_save_dir(dir)
+    {
+        errors.push(FieldError::new("save_dir", e));
+    }
+    for entry in config.resolve.as_deref().unwrap_or_default() {
+        if parse_resolve_entry(entry).is_none() {
+            errors.push(FieldError::new(
+                "resolve",
+                format!("invalid entry {entry:?}"),
+            ));
+        }
+    }
+    for pin in config.pinned_certs.as_deref().unwrap_or_default() {
+        if parse_pin(pin).is_none() {
+            errors.push(FieldError::new(
+                "pinned_certs",
+                format!("invalid pin {pin:?}"),
+            ));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_validate() {
+        let mut config = DownloadConfig::empty();
+        assert_eq!(validate(&config), Ok(()));
+        config.save_dir = Some(std::env::temp_dir().into());
+        config.http_proxy = Some(Arc::from("http://127.0.0.1:8080"));
+        assert_eq!(validate(&config), Ok(()));
+
+        config.all_proxy = Some(Arc::from("not a url"));
+        config.write_buffer_size = Some(0);
+        config.save_dir = Some(Path::new("/nonexistent/fast-down").into());
+        let fields: Vec<_> = validate(&config)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| e.field)
+            .collect();
+        assert_eq!(fields, ["all_proxy", "write_buffer_size", "save_dir"]);
+    }
+}
@@ -1,7 +1,11 @@
-use actix_web::{App, Error, HttpRequest, HttpResponse, HttpServer, get, rt, web};
+use actix_web::{
+    App, Error, HttpRequest, HttpResponse, HttpServer, delete, get, http::header::CONTENT_TYPE,
+    post, rt, web,
+};
 use actix_ws::AggregatedMessage;
+use aria2_gid::Gid;
 use futures_util::StreamExt as _;
-use server::{Downloader, config::DownloadConfig, entry::AddOptions};
+use server::{Downloader, config::DownloadConfig, input_file::InputSource, schedule::ScheduleSpec};
 use spin::mutex::SpinMutex;
 use std::sync::{Arc, LazyLock};
 
@@ -25,12 +29,97 @@ async fn echo(req: HttpRequest, stream: web::Payload) -> Result<HttpResponse, Er
     Ok(res)
 }
 
+/// Not a CORS-safelisted type, so browsers preflight cross-site requests,
+/// which this server does not answer.
+const INPUT_FILE_TYPE: &str = "application/x-aria2-input";
+
+#[post("/import")]
+async fn import(
+    req: HttpRequest,
+    downloader: web::Data<Arc<Downloader>>,
+    body: String,
+) -> HttpResponse {
+    let content_type = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next());
+    if content_type.is_none_or(|mime| !mime.trim().eq_ignore_ascii_case(INPUT_FILE_TYPE)) {
+        return HttpResponse::UnsupportedMediaType().body(format!("expected {INPUT_FILE_TYPE}"));
+    }
+    let downloader = downloader.get_ref().clone();
+    match downloader.import_input_file(&body, InputSource::Remote) {
+        Ok((group, gids)) => HttpResponse::Ok().json(serde_json::json!({
+            "group": group.to_string(),
+            "gids": gids.iter().map(Gid::to_string).collect::<Vec<_>>(),
+        })),
+        Err(e) => HttpResponse::BadRequest().body(format!("{e:?}")),
+    }
+}
+
+#[get("/schedules")]
+async fn list_schedules(downloader: web::Data<Arc<Downloader>>) -> HttpResponse {
+    let schedules: Vec<_> = downloader
+        .schedules()
+        .iter()
+        .map(|task| {
+            serde_json::json!({
+                "gid": task.gid.to_string(),
+                "spec": task.spec.to_json(),
+                "next_run": task.next_run.map(|t| t.unix_timestamp()),
+                "last_task": task.last_task.map(|gid| gid.to_string()),
+            })
+        })
+        .collect();
+    HttpResponse::Ok().json(schedules)
+}
+
+#[post("/schedules")]
+async fn add_schedule(
+    downloader: web::Data<Arc<Downloader>>,
+    body: web::Json<serde_json::Value>,
+) -> HttpResponse {
+    match ScheduleSpec::from_json(&body) {
+        Some(spec) => HttpResponse::Ok().json(downloader.add_schedule(spec).await.to_string()),
+        None => HttpResponse::BadRequest().body("invalid schedule"),
+    }
+}
+
+#[delete("/schedules/{gid}")]
+async fn cancel_schedule(
+    downloader: web::Data<Arc<Downloader>>,
+    gid: web::Path<String>,
+) -> HttpResponse {
+    let task = downloader
+        .schedules()
+        .into_iter()
+        .find(|task| task.gid.to_string() == *gid);
+    match task {
+        Some(task) if downloader.cancel_schedule(task.gid).await => {
+            HttpResponse::NoContent().finish()
+        }
+        _ => HttpResponse::NotFound().finish(),
+    }
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let global_config = Arc::new(SpinMutex::new(DownloadConfig::default()));
     let downloader = Arc::new(Downloader::new(global_config));
-    HttpServer::new(|| App::new().service(echo))
-        .bind(("127.0.0.1", 8080))?
-        .run()
-        .await
+    if let Err(e) = downloader.load_schedules("schedules.json").await {
+        log::error!("main(): Cannot load schedules: {e:?}");
+    }
+    rt::spawn(downloader.clone().run_schedules());
+    HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(downloader.clone()))
+            .service(echo)
+            .service(import)
+            .service(list_schedules)
+            .service(add_schedule)
+            .service(cancel_schedule)
+    })
+    .bind(("127.0.0.1", 8080))?
+    .run()
+    .await
 }